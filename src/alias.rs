@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+
+use crate::command::{find_command, CommandError, ParseError};
+
+///// ALIASES /////
+// [aliases] in the config gives one or more commands a name, ex:
+//
+// [aliases]
+// chill = 'playlist "Lo-fi Focus" ; shuffle on ; volume 40'
+// v = "volume $1"
+//
+// $1, $2.. are the words after the alias and $* all of them, when there is
+// neither they go after the last command. an alias can use other aliases but
+// not itself, and a command wins over an alias with the same name
+
+// so a = "b ; b", b = "c ; c".. doesn't go on forever, every command and
+// every alias used on the way counts, an alias can expand to nothing
+const MAX_COMMANDS: usize = 100;
+
+// the commands a line stands for, in order, with the aliases filled in
+pub fn expand(input: &str, aliases: &HashMap<String, String>) -> Result<Vec<String>, CommandError> {
+    let mut commands = vec![];
+    expand_into(input, aliases, &mut vec![], &mut commands, &mut 0)?;
+    Ok(commands)
+}
+
+fn expand_into(
+    input: &str,
+    aliases: &HashMap<String, String>,
+    expanding: &mut Vec<String>,
+    commands: &mut Vec<String>,
+    steps: &mut usize,
+) -> Result<(), CommandError> {
+    for line in split_commands(input) {
+        let words = shell_words::split(&line).map_err(|_| ParseError::UnclosedQuote)?;
+        let (name, args) = match words.split_first() {
+            Some(split) => split,
+            None => continue,
+        };
+
+        *steps += 1;
+        if *steps > MAX_COMMANDS {
+            return Err(CommandError::Usage(format!(
+                "Aliases can't run more than {} commands at once",
+                MAX_COMMANDS
+            )));
+        }
+
+        let body = match aliases.get(name) {
+            Some(body) if find_command(name).is_none() => body,
+            _ => {
+                commands.push(line);
+                continue;
+            }
+        };
+
+        if expanding.contains(name) {
+            return Err(CommandError::Usage(format!(
+                "Alias {} uses itself: {} -> {}",
+                name,
+                expanding.join(" -> "),
+                name
+            )));
+        }
+
+        expanding.push(name.clone());
+        expand_into(&substitute(body, args), aliases, expanding, commands, steps)?;
+        expanding.pop();
+    }
+
+    Ok(())
+}
+
+// splits on ; but not on one in quotes, playlist "a;b" stays one command
+fn split_commands(input: &str) -> Vec<String> {
+    let mut commands = vec![];
+    let mut command = String::new();
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+
+    for c in input.chars() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' && quote != Some('\'') {
+            escaped = true;
+        } else if quote == Some(c) {
+            quote = None;
+        } else if quote.is_none() && (c == '\'' || c == '"') {
+            quote = Some(c);
+        } else if quote.is_none() && c == ';' {
+            commands.push(command.trim().to_string());
+            command.clear();
+            continue;
+        }
+        command.push(c);
+    }
+    commands.push(command.trim().to_string());
+
+    commands
+        .into_iter()
+        .filter(|command| !command.is_empty())
+        .collect()
+}
+
+// fills in $1.. and $*, the words are quoted so they stay what they were
+fn substitute(body: &str, args: &[String]) -> String {
+    let mut expanded = String::new();
+    let mut used_args = false;
+    let mut chars = body.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            expanded.push(c);
+            continue;
+        }
+
+        match chars.peek() {
+            Some('*') => {
+                chars.next();
+                used_args = true;
+                expanded += &quote_all(args);
+            }
+            Some(next) if next.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(digit) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                    number.push(*digit);
+                    chars.next();
+                }
+                used_args = true;
+                // $0 and ones that weren't given are left out
+                if let Some(arg) = number
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|n| n.checked_sub(1))
+                    .and_then(|i| args.get(i))
+                {
+                    expanded += &shell_words::quote(arg);
+                }
+            }
+            _ => expanded.push(c),
+        }
+    }
+
+    if !used_args && !args.is_empty() {
+        expanded = format!("{} {}", expanded, quote_all(args));
+    }
+    expanded
+}
+
+fn quote_all(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| shell_words::quote(arg).to_string())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aliases(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, body)| (name.to_string(), body.to_string()))
+            .collect()
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn splits_on_semicolons() {
+        assert_eq!(split_commands("next ; volume 40"), ["next", "volume 40"]);
+        assert_eq!(split_commands("next;pause"), ["next", "pause"]);
+        // empty commands are dropped
+        assert_eq!(split_commands(" ; next ;; "), ["next"]);
+        assert!(split_commands("").is_empty());
+    }
+
+    #[test]
+    fn keeps_quoted_semicolons() {
+        assert_eq!(
+            split_commands(r#"playlist "a;b" ; next"#),
+            [r#"playlist "a;b""#, "next"]
+        );
+        assert_eq!(split_commands("song 'a;b'"), ["song 'a;b'"]);
+        assert_eq!(
+            split_commands(r#"song "it's;here""#),
+            [r#"song "it's;here""#]
+        );
+        assert_eq!(
+            split_commands(r#"song 'say "hi;"'"#),
+            [r#"song 'say "hi;"'"#]
+        );
+    }
+
+    #[test]
+    fn keeps_escaped_semicolons() {
+        assert_eq!(split_commands(r"song a\;b ; next"), [r"song a\;b", "next"]);
+        assert_eq!(
+            split_commands(r#"song "a\";b" ; next"#),
+            [r#"song "a\";b""#, "next"]
+        );
+        // a backslash means nothing in single quotes
+        assert_eq!(split_commands(r"song 'a\' ; next"), [r"song 'a\'", "next"]);
+    }
+
+    #[test]
+    fn unclosed_quotes_stay_one_command() {
+        assert_eq!(split_commands(r#"song "a ; next"#), [r#"song "a ; next"#]);
+    }
+
+    #[test]
+    fn substitutes_numbered_args() {
+        assert_eq!(substitute("volume $1", &args(&["40"])), "volume 40");
+        assert_eq!(substitute("seek $2:$1", &args(&["30", "1"])), "seek 1:30");
+        // the same one twice
+        assert_eq!(substitute("song $1 $1", &args(&["a"])), "song a a");
+    }
+
+    #[test]
+    fn quotes_args_with_spaces() {
+        assert_eq!(
+            substitute("activate $1", &args(&["Kitchen speaker"])),
+            "activate 'Kitchen speaker'"
+        );
+        assert_eq!(
+            substitute("song $*", &args(&["it's", "a; b"])),
+            r"song 'it'\''s' 'a; b'"
+        );
+    }
+
+    #[test]
+    fn substitutes_all_args() {
+        assert_eq!(
+            substitute("song Daft Punk $*", &args(&["one", "more", "time"])),
+            "song Daft Punk one more time"
+        );
+        assert_eq!(substitute("song $*", &[]), "song ");
+    }
+
+    #[test]
+    fn leaves_out_zero_and_missing_args() {
+        assert_eq!(substitute("volume $0", &args(&["40"])), "volume ");
+        assert_eq!(substitute("volume $2", &args(&["40"])), "volume ");
+        assert_eq!(substitute("volume $1", &[]), "volume ");
+    }
+
+    #[test]
+    fn reads_every_digit_of_a_number() {
+        let ten = args(&["1", "2", "3", "4", "5", "6", "7", "8", "9", "10"]);
+        assert_eq!(substitute("volume $10", &ten), "volume 10");
+        // $10 is not $1 followed by a 0
+        assert_eq!(substitute("volume $10", &args(&["4"])), "volume ");
+        assert_eq!(substitute("seek $99999999999999999999999", &ten), "seek ");
+    }
+
+    #[test]
+    fn plain_dollars_stay() {
+        assert_eq!(substitute("song $ $a", &[]), "song $ $a");
+        assert_eq!(substitute("song $", &args(&["x"])), "song $ x");
+    }
+
+    #[test]
+    fn appends_args_without_placeholders() {
+        assert_eq!(substitute("playlist", &args(&["lo-fi"])), "playlist lo-fi");
+        assert_eq!(
+            substitute("next ; playlist", &args(&["Deep Work"])),
+            "next ; playlist 'Deep Work'"
+        );
+        assert_eq!(substitute("next", &[]), "next");
+    }
+
+    #[test]
+    fn expands_aliases() {
+        let aliases = aliases(&[
+            (
+                "chill",
+                r#"playlist "Lo-fi Focus" ; shuffle on ; volume 40"#,
+            ),
+            ("v", "volume $1"),
+        ]);
+        assert_eq!(
+            expand("chill ; v 20", &aliases).unwrap(),
+            [
+                r#"playlist "Lo-fi Focus""#,
+                "shuffle on",
+                "volume 40",
+                "volume 20"
+            ]
+        );
+        assert_eq!(expand("next", &aliases).unwrap(), ["next"]);
+        assert!(expand("", &aliases).unwrap().is_empty());
+    }
+
+    #[test]
+    fn aliases_use_other_aliases() {
+        let aliases = aliases(&[
+            ("loud", "v 90"),
+            ("v", "volume $1"),
+            ("party", "loud ; next"),
+        ]);
+        assert_eq!(expand("party", &aliases).unwrap(), ["volume 90", "next"]);
+    }
+
+    #[test]
+    fn commands_win_over_aliases() {
+        let aliases = aliases(&[("next", "prev")]);
+        assert_eq!(expand("next", &aliases).unwrap(), ["next"]);
+    }
+
+    #[test]
+    fn an_alias_can_be_used_twice_in_a_row() {
+        let aliases = aliases(&[("v", "volume $1"), ("both", "v 10 ; v 20")]);
+        assert_eq!(
+            expand("both", &aliases).unwrap(),
+            ["volume 10", "volume 20"]
+        );
+    }
+
+    #[test]
+    fn finds_recursion() {
+        let itself = aliases(&[("loop", "loop")]);
+        let err = expand("loop", &itself).unwrap_err().to_string();
+        assert_eq!(err, "Alias loop uses itself: loop -> loop");
+
+        let cycle = aliases(&[("one", "next ; two"), ("two", "three"), ("three", "one")]);
+        let err = expand("one", &cycle).unwrap_err().to_string();
+        assert_eq!(err, "Alias one uses itself: one -> two -> three -> one");
+        assert_eq!(expand("one", &cycle).unwrap_err().exit_code(), 2);
+    }
+
+    #[test]
+    fn caps_the_number_of_commands() {
+        // every level doubles, 2^7 = 128 commands
+        let aliases = aliases(&[
+            ("l1", "l2 ; l2"),
+            ("l2", "l3 ; l3"),
+            ("l3", "l4 ; l4"),
+            ("l4", "l5 ; l5"),
+            ("l5", "l6 ; l6"),
+            ("l6", "l7 ; l7"),
+            ("l7", "next ; next"),
+        ]);
+        // 32 commands and the 31 aliases that got there
+        assert_eq!(expand("l3", &aliases).unwrap().len(), 32);
+        let err = expand("l1", &aliases).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Aliases can't run more than {} commands at once",
+                MAX_COMMANDS
+            )
+        );
+
+        let line = vec!["next"; MAX_COMMANDS].join(" ; ");
+        assert_eq!(expand(&line, &HashMap::new()).unwrap().len(), MAX_COMMANDS);
+        let line = vec!["next"; MAX_COMMANDS + 1].join(" ; ");
+        assert!(expand(&line, &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn caps_aliases_that_expand_to_nothing() {
+        // no command is ever pushed, the aliases on the way still count
+        let mut aliases = aliases(&[("l20", "")]);
+        for level in 1..20 {
+            aliases.insert(
+                format!("l{}", level),
+                format!("l{next} ; l{next}", next = level + 1),
+            );
+        }
+        let err = expand("l1", &aliases).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Aliases can't run more than {} commands at once",
+                MAX_COMMANDS
+            )
+        );
+
+        // a few empty ones are fine
+        assert!(expand("l18", &aliases).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unclosed_quotes_are_errors() {
+        let err = expand(r#"song "never"#, &HashMap::new()).unwrap_err();
+        assert_eq!(err.exit_code(), 2);
+    }
+}
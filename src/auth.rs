@@ -0,0 +1,468 @@
+use rspotify::{
+    http::HttpError, prelude::*, AuthCodePkceSpotify, AuthCodeSpotify, ClientCredsSpotify,
+    ClientError, ClientResult, Config, Credentials, OAuth, Token, TokenCallback,
+};
+use std::{
+    collections::HashSet,
+    fs,
+    future::Future,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use terminal_spotify::{can_prompt, eprint_err, eprintf_err, user_input};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::{
+    callback::wait_for_token,
+    command::CommandError,
+    config::{config_dir, profile_dir, profiles_dir, AppConfig, Profile, DEFAULT_PROFILE},
+    credentials::{delete_all_secrets, delete_secret, get_secret, set_secret, REFRESH_TOKEN},
+    scopes::{to_scope_set, BASE_SCOPES},
+};
+
+// has to be &str can't call String::from outside fn ?
+pub const REDIRECT_URI: &str = "http://localhost:8888/callback";
+
+const TOKEN_CACHE_FILE: &str = "token_cache.json";
+
+pub fn token_cache_path(profile: &str) -> PathBuf {
+    profile_dir(profile).join(TOKEN_CACHE_FILE)
+}
+
+// before there were profiles the cache was right in the config folder, it
+// belongs to the default profile now
+fn migrate_token_cache() {
+    let old_path = config_dir().join(TOKEN_CACHE_FILE);
+    let new_path = token_cache_path(DEFAULT_PROFILE);
+    if !old_path.exists() || new_path.exists() {
+        return;
+    }
+
+    let moved = fs::create_dir_all(profile_dir(DEFAULT_PROFILE))
+        .and_then(|_| fs::rename(&old_path, &new_path));
+    if let Err(err) = moved {
+        eprintf_err(
+            "Could not move the old token cache to the default profile",
+            err,
+        );
+    }
+}
+
+///// TOKEN CACHE /////
+// the cache is written here instead of by rspotify so the file gets 0600 and
+// the refresh token can be kept in the keyring instead of the file
+fn write_token_cache(path: &Path, token: &Token) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    {
+        // in case the file was created with other permissions before
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+
+    file.write_all(serde_json::to_string(token)?.as_bytes())
+}
+
+// the refresh token is looked up in the keyring if the file doesn't have it
+async fn read_token_cache(profile: &str, use_keyring: bool) -> Option<Token> {
+    if profile == DEFAULT_PROFILE {
+        migrate_token_cache();
+    }
+    let mut token = Token::from_cache(token_cache_path(profile)).ok()?;
+
+    if use_keyring && token.refresh_token.is_none() {
+        token.refresh_token = get_secret(profile, REFRESH_TOKEN).await.ok().flatten();
+    }
+
+    Some(token)
+}
+
+// called by rspotify every time a token is requested or refreshed. it can't
+// wait for the keyring, so the whole token goes in the file first and the
+// refresh token is handed to keyring_writer, which takes it out of the file
+// once the keyring has it
+fn store_token(profile: &str, keyring: Option<&UnboundedSender<Token>>, token: Token) {
+    if let Err(err) = write_token_cache(&token_cache_path(profile), &token) {
+        eprintf_err(
+            "Could not write token cache, you will have to log in again next time",
+            err,
+        );
+    }
+
+    if let Some(keyring) = keyring {
+        if token.refresh_token.is_some() {
+            let _ = keyring.send(token);
+        }
+    }
+}
+
+async fn keyring_writer(profile: String, mut tokens: UnboundedReceiver<Token>) {
+    while let Some(mut token) = tokens.recv().await {
+        // only the newest one matters
+        while let Ok(newer) = tokens.try_recv() {
+            token = newer;
+        }
+
+        let refresh_token = match token.refresh_token.take() {
+            Some(refresh_token) => refresh_token,
+            None => continue,
+        };
+
+        match set_secret(&profile, REFRESH_TOKEN, &refresh_token).await {
+            Ok(_) => {
+                if let Err(err) = write_token_cache(&token_cache_path(&profile), &token) {
+                    eprintf_err("Could not write token cache", err);
+                }
+            }
+            Err(err) => eprintf_err(
+                "Could not store the refresh token in the keyring, keeping it in the token cache",
+                err,
+            ),
+        }
+    }
+}
+
+fn oauth(redirect_uri: String) -> OAuth {
+    OAuth {
+        redirect_uri,
+        scopes: to_scope_set(BASE_SCOPES),
+        ..Default::default()
+    }
+}
+
+// the clients a user can log in with, lets the scopes be changed after login
+pub trait Authorize: OAuthClient {
+    fn oauth_mut(&mut self) -> &mut OAuth;
+    fn authorize_url(&mut self) -> ClientResult<String>;
+}
+
+impl Authorize for AuthCodeSpotify {
+    fn oauth_mut(&mut self) -> &mut OAuth {
+        &mut self.oauth
+    }
+
+    // show_dialog so the user gets a chance to pick another account than the
+    // one already logged in in the browser
+    fn authorize_url(&mut self) -> ClientResult<String> {
+        self.get_authorize_url(true)
+    }
+}
+
+impl Authorize for AuthCodePkceSpotify {
+    fn oauth_mut(&mut self) -> &mut OAuth {
+        &mut self.oauth
+    }
+
+    // rspotify has no show_dialog option for pkce
+    fn authorize_url(&mut self) -> ClientResult<String> {
+        Ok(self.get_authorize_url(None)? + "&show_dialog=true")
+    }
+}
+
+fn cache_config(profile: &str, use_keyring: bool) -> Config {
+    let keyring = use_keyring.then(|| {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(keyring_writer(profile.to_string(), receiver));
+        sender
+    });
+
+    let profile = profile.to_string();
+    let callback = TokenCallback(Box::new(move |token| {
+        store_token(&profile, keyring.as_ref(), token);
+        Ok(())
+    }));
+
+    Config {
+        token_refreshing: true,
+        token_callback_fn: Arc::new(Some(callback)),
+        ..Default::default()
+    }
+}
+
+pub async fn authorize_user(
+    client_id: &str,
+    client_secret: &str,
+    profile_name: &str,
+    profile: &Profile,
+) -> Result<AuthCodeSpotify, Box<dyn std::error::Error>> {
+    let creds = Credentials::new(client_id, client_secret);
+    let config = cache_config(profile_name, profile.keyring);
+    let mut spotify = AuthCodeSpotify::with_config(creds, oauth(profile.redirect_uri()), config);
+
+    if load_cached_token(&spotify, profile_name, profile.keyring).await {
+        return Ok(spotify);
+    }
+
+    let url = spotify.authorize_url()?;
+    login(&spotify, &url).await?;
+
+    Ok(spotify)
+}
+
+// pkce only needs the client id, the secret is replaced by a code verifier
+// generated for every login
+pub async fn authorize_user_pkce(
+    client_id: &str,
+    profile_name: &str,
+    profile: &Profile,
+) -> Result<AuthCodePkceSpotify, Box<dyn std::error::Error>> {
+    let creds = Credentials::new_pkce(client_id);
+    let config = cache_config(profile_name, profile.keyring);
+    let mut spotify =
+        AuthCodePkceSpotify::with_config(creds, oauth(profile.redirect_uri()), config);
+
+    if load_cached_token(&spotify, profile_name, profile.keyring).await {
+        return Ok(spotify);
+    }
+
+    let url = spotify.authorize_url()?;
+    login(&spotify, &url).await?;
+
+    Ok(spotify)
+}
+
+// read-only access without a user, enough for searching and browsing
+pub async fn authorize_client_creds(
+    client_id: &str,
+    client_secret: &str,
+) -> Result<ClientCredsSpotify, Box<dyn std::error::Error>> {
+    let creds = Credentials::new(client_id, client_secret);
+    let spotify = ClientCredsSpotify::new(creds);
+
+    spotify.request_token().await?;
+
+    Ok(spotify)
+}
+
+async fn login(spotify: &impl OAuthClient, url: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let err = match wait_for_token(spotify, url).await {
+        Ok(_) => return Ok(()),
+        // pasting the url needs someone at the terminal
+        Err(err) if !can_prompt() => return Err(err),
+        Err(err) => err.to_string(),
+    };
+
+    // everything goes to stderr so it doesn't end up in --output json
+    eprintf_err("Automatic login failed", err);
+    eprintln!("Falling back to pasting the redirect url manually");
+    eprintln!("Open this url in your browser to log in: {}", url);
+    eprint!("Paste the url you were redirected to: ");
+    std::io::stderr().flush().unwrap();
+
+    let code = spotify
+        .parse_response_code(user_input().trim())
+        .ok_or("The url had no code or an invalid state")?;
+    spotify.request_token(&code).await?;
+
+    Ok(())
+}
+
+pub async fn granted_scopes(spotify: &impl BaseClient) -> HashSet<String> {
+    match spotify.get_token().lock().await.unwrap().as_ref() {
+        Some(token) => token.scopes.clone(),
+        None => HashSet::new(),
+    }
+}
+
+// asks to log in again if the token is missing any of the scopes, fails if
+// the command can't run without them
+pub async fn ensure_scopes(
+    spotify: &mut impl Authorize,
+    needed: &[&'static str],
+) -> Result<(), CommandError> {
+    let granted = granted_scopes(spotify).await;
+    let missing = missing_scopes(&granted, needed);
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    if !ask_for_scopes(
+        "This command needs permissions you have not granted yet",
+        &missing,
+    ) {
+        return Err(CommandError::MissingScopes(missing));
+    }
+
+    // keep everything that was granted before so other commands keep working
+    let mut scopes = spotify.get_oauth().scopes.clone();
+    scopes.extend(granted);
+    scopes.extend(to_scope_set(needed));
+
+    upgrade_scopes(spotify, scopes)
+        .await
+        .map_err(|err| CommandError::failed("Could not log in again", err))
+}
+
+pub fn missing_scopes(granted: &HashSet<String>, needed: &[&'static str]) -> Vec<&'static str> {
+    needed
+        .iter()
+        .filter(|scope| !granted.contains(**scope))
+        .copied()
+        .collect()
+}
+
+// false when there is no one to ask or the answer is no
+fn ask_for_scopes(reason: &str, missing: &[&str]) -> bool {
+    if !can_prompt() {
+        return false;
+    }
+
+    // on stderr, stdout may be json
+    eprintln!("{}: {}", reason, missing.join(", "));
+    eprint!("Log in again to grant them? [Y/n] ");
+    std::io::stderr().flush().unwrap();
+
+    let input = user_input();
+    !(input.trim().to_lowercase() == "n" || input.trim().to_lowercase() == "no")
+}
+
+async fn upgrade_scopes(
+    spotify: &mut impl Authorize,
+    scopes: HashSet<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    spotify.oauth_mut().scopes = scopes;
+
+    let url = spotify.authorize_url()?;
+    login(spotify, &url).await
+}
+
+///// LOGOUT /////
+// spotify has no endpoint for revoking tokens, so logging out means forgetting
+// the cached token, the next start goes through the browser login again
+pub async fn logout(profile: &str) -> Result<(), Box<dyn std::error::Error>> {
+    match fs::remove_file(token_cache_path(profile)) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+        _ => (),
+    }
+
+    // no keyring means there is nothing stored in it either
+    if uses_keyring(profile) {
+        if let Err(err) = delete_secret(profile, REFRESH_TOKEN).await {
+            eprintf_err("Could not remove the refresh token from the keyring", err);
+        }
+    }
+
+    Ok(())
+}
+
+// logs out of every profile that has logged in on this machine and removes
+// every secret stored in the keyring
+pub async fn logout_all() -> Result<(), Box<dyn std::error::Error>> {
+    let profiles_dir = profiles_dir();
+    if profiles_dir.exists() {
+        for entry in fs::read_dir(profiles_dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            match fs::remove_file(token_cache_path(&entry.file_name().to_string_lossy())) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => (),
+            }
+        }
+    }
+
+    // `credentials set` can have used the keyring without keyring = true, but
+    // it's only worth complaining about for the profiles that use it
+    let config = AppConfig::load().unwrap_or_default();
+    let any_keyring = config.profiles.values().any(|profile| profile.keyring);
+    match delete_all_secrets().await {
+        Err(err) if any_keyring => eprintf_err("Could not clear the keyring", err),
+        _ => (),
+    }
+
+    Ok(())
+}
+
+fn uses_keyring(profile: &str) -> bool {
+    AppConfig::load()
+        .ok()
+        .and_then(|config| config.profile(profile))
+        .is_some_and(|profile| profile.keyring)
+}
+
+// loads the cached token and refreshes it if it has expired, returns false
+// when the user has to go through the browser login again
+async fn load_cached_token(spotify: &impl OAuthClient, profile: &str, use_keyring: bool) -> bool {
+    let token = match read_token_cache(profile, use_keyring).await {
+        Some(token) => token,
+        None => return false,
+    };
+
+    let expired = token.is_expired();
+    *spotify.get_token().lock().await.unwrap() = Some(token);
+
+    if !expired {
+        return true;
+    }
+
+    match refresh(spotify).await {
+        Ok(_) => true,
+        Err(err) => {
+            eprintf_err("Could not refresh the cached token", err);
+            false
+        }
+    }
+}
+
+// rspotify leaves no token at all when there is no refresh token to use, and
+// panics on the next request. the old one is put back instead so that request
+// gets a 401
+async fn refresh(spotify: &impl BaseClient) -> Result<(), String> {
+    let old_token = spotify.get_token().lock().await.unwrap().clone();
+
+    let err = match spotify.refresh_token().await {
+        Ok(_) if spotify.get_token().lock().await.unwrap().is_some() => return Ok(()),
+        Ok(_) => String::from("there is no refresh token"),
+        Err(err) => err.to_string(),
+    };
+
+    *spotify.get_token().lock().await.unwrap() = old_token;
+    Err(err)
+}
+
+fn is_unauthorized(err: &ClientError) -> bool {
+    match err {
+        ClientError::Http(http_err) => match http_err.as_ref() {
+            HttpError::StatusCode(response) => response.status() == 401,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+// retries the request once with a refreshed token if spotify answers 401,
+// the expiry check rspotify does on its own does not catch revoked tokens
+pub async fn reauth<T, F, Fut>(spotify: &impl BaseClient, request: F) -> ClientResult<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = ClientResult<T>>,
+{
+    match request().await {
+        Err(err) if is_unauthorized(&err) => {
+            if refresh(spotify).await.is_err() {
+                eprint_err(
+                    "Session expired and the token could not be refreshed, restart to log in again",
+                );
+                return Err(err);
+            }
+            request().await
+        }
+        res => res,
+    }
+}
@@ -0,0 +1,250 @@
+use colored::Colorize;
+use rspotify::{
+    model::{PlayableItem, SearchResult, SearchType, SimplifiedPlaylist},
+    prelude::*,
+    ClientCredsSpotify,
+};
+use terminal_spotify::{format_duration, print_err, select, you_can_not_leave};
+
+use crate::{
+    alias,
+    auth::reauth,
+    command::{help_text, parse_input, Command, CommandError, Reply},
+    config::AppConfig,
+    credentials::credentials_command,
+    editor::{Completions, HistoryKind, LineEditor},
+    find_albums, find_songs, query_or_ask, select_profile,
+};
+
+///// READ-ONLY MODE /////
+// client credentials can only see the public catalog, so everything that
+// needs a user (player, devices, private playlists) is turned off
+pub async fn run_read_only(
+    spotify: ClientCredsSpotify,
+    profile_name: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    println!(
+        "{}",
+        "Read-only mode, you can search and browse but not control playback".yellow()
+    );
+
+    let mut editor = LineEditor::open(profile_name, HistoryKind::Commands)?;
+    let prompt = format!("{} ", "->".bold().bright_green());
+
+    let aliases = AppConfig::load().unwrap_or_default().aliases(profile_name);
+
+    'session: loop {
+        // picks up the searches made since the last prompt
+        editor.complete_with(Completions::default());
+
+        // ctrl-d ends the session
+        let input = match editor.read(&prompt) {
+            Some(input) => input,
+            None => break,
+        };
+        let lines = match alias::expand(&input, &aliases) {
+            Ok(lines) => lines,
+            Err(err) => {
+                print_err(&err.to_string());
+                continue;
+            }
+        };
+
+        for line in lines {
+            let command = match parse_input(&line) {
+                Some(command) => command,
+                None => break,
+            };
+
+            match execute_read_only(&spotify, profile_name, command).await {
+                Ok(Reply::Done) => (),
+                Ok(Reply::Message(message)) | Ok(Reply::Data { text: message, .. }) => {
+                    println!("{}", message)
+                }
+                Ok(Reply::Exit(_)) => break 'session,
+                Ok(Reply::SwitchProfile(next_profile)) => return Ok(Some(next_profile)),
+                Err(err) => {
+                    print_err(&err.to_string());
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+pub async fn execute_read_only(
+    spotify: &ClientCredsSpotify,
+    profile_name: &str,
+    command: Command,
+) -> Result<Reply, CommandError> {
+    match command {
+        Command::Help => Ok(Reply::Message(help_text(true))),
+        Command::Song(query) => {
+            let q = query_or_ask(query, "Search for a song", profile_name)?;
+            lookup_song(spotify, &q).await
+        }
+        Command::Album(query) => {
+            let q = query_or_ask(query, "Search for an album", profile_name)?;
+            lookup_album(spotify, &q).await
+        }
+        Command::Playlist(query) => {
+            let q = query_or_ask(query, "Search for a playlist", profile_name)?;
+            lookup_playlist(spotify, &q).await
+        }
+        Command::Credentials(action) => credentials_command(profile_name, action)
+            .await
+            .map(Reply::Message),
+        Command::Profile(name) => select_profile(profile_name, name.as_deref()),
+        Command::Exit => {
+            if you_can_not_leave() {
+                return Ok(Reply::Exit(None));
+            }
+            Ok(Reply::Done)
+        }
+        // everything else needs a user
+        command => Err(CommandError::Usage(format!(
+            "Not available in read-only mode, start without --read-only to log in: {}",
+            command.name()
+        ))),
+    }
+}
+
+// without a terminal to pick in the top result is shown
+async fn lookup_song(spotify: &ClientCredsSpotify, query: &str) -> Result<Reply, CommandError> {
+    let songs = find_songs(spotify, query).await;
+    if songs.is_empty() {
+        return Err(CommandError::NotFound("No songs found".to_string()));
+    }
+
+    let song_names: Vec<String> = songs
+        .iter()
+        .map(|song| format!("{} - {}", song.song_name, song.artists.join(", ")))
+        .collect();
+
+    let selection = select("Select song", &song_names).unwrap_or(0);
+
+    let song = &songs[selection];
+    let text = format!(
+        "{}\nArtists: {}\nAlbum: {}\nLength: {}",
+        song.song_name.bold(),
+        song.artists.join(", "),
+        song.album,
+        format_duration(song.duration)
+    );
+    Ok(Reply::data(text, song))
+}
+
+async fn lookup_album(spotify: &ClientCredsSpotify, query: &str) -> Result<Reply, CommandError> {
+    let albums = find_albums(spotify, query).await;
+    if albums.is_empty() {
+        return Err(CommandError::NotFound("No albums found".to_string()));
+    }
+
+    let album_names: Vec<String> = albums
+        .iter()
+        .map(|album| format!("{} - {}", album.name, album.artists.join(", ")))
+        .collect();
+
+    let selection = select("Select album", &album_names).unwrap_or(0);
+
+    let album = reauth(spotify, || {
+        spotify.album(albums[selection].id.clone(), None)
+    })
+    .await
+    .map_err(|err| CommandError::failed("Could not get the album", err))?;
+
+    let mut text = format!(
+        "{} ({})\nArtists: {}",
+        album.name.bold(),
+        album.release_date,
+        album
+            .artists
+            .iter()
+            .map(|artist| artist.name.clone())
+            .collect::<Vec<String>>()
+            .join(", ")
+    );
+    for track in &album.tracks.items {
+        text += &format!(
+            "\n{:>3}. {} ({})",
+            track.track_number,
+            track.name,
+            format_duration(track.duration)
+        );
+    }
+
+    Ok(Reply::data(text, &album))
+}
+
+async fn lookup_playlist(spotify: &ClientCredsSpotify, query: &str) -> Result<Reply, CommandError> {
+    let res = reauth(spotify, || {
+        spotify.search(query, SearchType::Playlist, None, None, Some(5), None)
+    })
+    .await;
+
+    let playlists: Vec<SimplifiedPlaylist> = match res {
+        Ok(SearchResult::Playlists(playlists)) => playlists.items,
+        Ok(_) => vec![],
+        Err(err) => return Err(CommandError::failed("Could not search for playlists", err)),
+    };
+    if playlists.is_empty() {
+        return Err(CommandError::NotFound("No playlists found".to_string()));
+    }
+
+    let playlist_names: Vec<String> = playlists
+        .iter()
+        .map(|playlist| {
+            format!(
+                "{} - {}",
+                playlist.name,
+                playlist.owner.display_name.clone().unwrap_or_default()
+            )
+        })
+        .collect();
+
+    let selection = select("Select playlist", &playlist_names).unwrap_or(0);
+
+    let playlist = reauth(spotify, || {
+        spotify.playlist(playlists[selection].id.clone(), None, None)
+    })
+    .await
+    .map_err(|err| CommandError::failed("Could not get the playlist", err))?;
+
+    let mut text = format!(
+        "{} by {} ({} tracks)",
+        playlist.name.bold(),
+        playlist.owner.display_name.clone().unwrap_or_default(),
+        playlist.tracks.total
+    );
+    for (i, item) in playlist.tracks.items.iter().enumerate() {
+        match &item.track {
+            Some(PlayableItem::Track(track)) => {
+                text += &format!(
+                    "\n{:>3}. {} - {} ({})",
+                    i + 1,
+                    track.name,
+                    track
+                        .artists
+                        .iter()
+                        .map(|artist| artist.name.clone())
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                    format_duration(track.duration)
+                )
+            }
+            Some(PlayableItem::Episode(episode)) => {
+                text += &format!(
+                    "\n{:>3}. {} ({})",
+                    i + 1,
+                    episode.name,
+                    format_duration(episode.duration)
+                )
+            }
+            None => continue,
+        }
+    }
+
+    Ok(Reply::data(text, &playlist))
+}
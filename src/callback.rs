@@ -0,0 +1,132 @@
+use reqwest::Url;
+use rspotify::prelude::*;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+// how long to wait for the browser to hit the redirect uri
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(120);
+// how long one connection gets to send its request, so an idle one doesn't
+// hold up the browser
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SUCCESS_PAGE: &str = "Successfully authorized, you can return to the terminal";
+const FAILURE_PAGE: &str = "An error occured and you were not authorized, check the terminal";
+
+// listens on the redirect uri, opens the login page and exchanges the code
+// spotify redirects back with for a token
+pub async fn wait_for_token(
+    spotify: &impl OAuthClient,
+    auth_url: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let redirect_uri = Url::parse(&spotify.get_oauth().redirect_uri)?;
+    let host = redirect_uri.host_str().unwrap_or("localhost");
+    let port = redirect_uri.port_or_known_default().unwrap_or(8888);
+
+    let listener = TcpListener::bind((host, port)).await?;
+
+    match webbrowser::open(auth_url) {
+        Ok(_) => eprintln!("Opened {} in your browser.", auth_url),
+        Err(_) => eprintln!("Open this url in your browser to log in: {}", auth_url),
+    }
+    eprintln!("Waiting for the login to finish in the browser..");
+
+    match timeout(
+        CALLBACK_TIMEOUT,
+        accept_callback(spotify, &listener, &redirect_uri),
+    )
+    .await
+    {
+        Ok(res) => res,
+        Err(_) => Err("Timed out waiting for the login callback".into()),
+    }
+}
+
+async fn accept_callback(
+    spotify: &impl OAuthClient,
+    listener: &TcpListener,
+    redirect_uri: &Url,
+) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+
+        let target = match timeout(REQUEST_TIMEOUT, read_request_target(&mut stream)).await {
+            Ok(Some(target)) => target,
+            Ok(None) | Err(_) => continue,
+        };
+
+        // browsers also ask for /favicon.ico etc, and anything that isn't a
+        // request for the callback doesn't end the login
+        let url = match redirect_uri.join(&target) {
+            Ok(url) => url,
+            Err(_) => {
+                respond(&mut stream, "400 Bad Request", "Bad request").await;
+                continue;
+            }
+        };
+        if url.path() != redirect_uri.path() {
+            respond(&mut stream, "404 Not Found", "Not found").await;
+            continue;
+        }
+
+        // also checks that the state matches the one sent in the authorize url
+        let code = match spotify.parse_response_code(url.as_str()) {
+            Some(code) => code,
+            None => {
+                respond(&mut stream, "400 Bad Request", FAILURE_PAGE).await;
+                return Err("The callback had no code or an invalid state".into());
+            }
+        };
+
+        return match spotify.request_token(&code).await {
+            Ok(_) => {
+                respond(&mut stream, "200 OK", SUCCESS_PAGE).await;
+                Ok(())
+            }
+            Err(err) => {
+                respond(&mut stream, "500 Internal Server Error", FAILURE_PAGE).await;
+                Err(err.into())
+            }
+        };
+    }
+}
+
+// returns the path + query of the request line, ex: /callback?code=..&state=..
+async fn read_request_target(stream: &mut TcpStream) -> Option<String> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await.ok()?;
+
+    // read the rest of the headers so the browser doesn't get a reset connection
+    loop {
+        let mut header = String::new();
+        match reader.read_line(&mut header).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) if header.trim().is_empty() => break,
+            Ok(_) => continue,
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) => Some(target.to_string()),
+        _ => None,
+    }
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+    let page = format!("<html><body><h3>{}</h3></body></html>", body);
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        page.len(),
+        page
+    );
+
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
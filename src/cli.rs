@@ -0,0 +1,208 @@
+use clap::{Arg, ArgAction, ArgMatches};
+
+use crate::{
+    command::{find_command, parse, Command, ParseError, COMMANDS},
+    config::check_profile_name,
+    output::Output,
+};
+
+///// COMMAND LINE /////
+// `terminal-spotify` starts the repl, `terminal-spotify next` runs a single
+// command and exits, for scripts and keybindings
+
+// commands that only make sense inside the repl
+pub const REPL_ONLY: &[&str] = &["help", "exit", "profile"];
+
+// the subcommands are made from the same definitions as the repl commands
+pub fn cli() -> clap::Command {
+    let mut cli = clap::Command::new("terminal-spotify")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Control spotify from the terminal, starts the repl if no command is given")
+        .after_help("Exit codes: 0 ok, 1 failed, 2 usage, 3 no active device, 4 nothing found, 5 needs a terminal to ask in, 6 missing permissions")
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_name("NAME")
+                .global(true)
+                .value_parser(|name: &str| check_profile_name(name).map(|_| name.to_string()))
+                .help("Profile from config.toml to use"),
+        )
+        .arg(
+            Arg::new("pkce")
+                .long("pkce")
+                .action(ArgAction::SetTrue)
+                .global(true)
+                .help("Log in with PKCE, only needs a client id"),
+        )
+        .arg(
+            Arg::new("read-only")
+                .long("read-only")
+                .action(ArgAction::SetTrue)
+                .global(true)
+                .help("Only search and browse, without logging in a user"),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .value_name("FORMAT")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .global(true)
+                .help("Print a json object for every command, for scripts"),
+        );
+
+    for def in COMMANDS.iter().filter(|def| !REPL_ONLY.contains(&def.name)) {
+        let mut subcommand = clap::Command::new(def.name)
+            .about(def.description)
+            .visible_aliases(def.aliases);
+
+        // `[--json] [--format <template>]` are options, anything else is taken
+        // as words
+        if let Some(options) = options(def.args) {
+            for (name, value) in options {
+                subcommand = subcommand.arg(match value {
+                    Some(value) => Arg::new(name).long(name).value_name(value),
+                    None => Arg::new(name).long(name).action(ArgAction::SetTrue),
+                });
+            }
+        } else if !def.args.is_empty() {
+            subcommand = subcommand.arg(
+                Arg::new("args")
+                    .value_name(def.args.trim_matches(['[', ']', '<', '>']))
+                    .num_args(0..),
+            );
+        }
+
+        cli = cli.subcommand(subcommand);
+    }
+
+    cli.subcommand(
+        clap::Command::new("daemon")
+            .about(
+                "keep the session running in the background and take commands over a unix socket",
+            )
+            .arg(
+                Arg::new("http")
+                    .long("http")
+                    .action(ArgAction::SetTrue)
+                    .help("Also serve the rest api, see [http] in config.toml"),
+            ),
+    )
+    .subcommand(
+        clap::Command::new("ctl")
+            .about("send a command to the daemon, ex: ctl play \"album:Discovery\"")
+            .arg(
+                Arg::new("command")
+                    .value_name("COMMAND")
+                    .required(true)
+                    .num_args(1..)
+                    .trailing_var_arg(true)
+                    .allow_hyphen_values(true),
+            ),
+    )
+    .subcommand(clap::Command::new("tui").about(
+        "start the full screen interface instead of the repl, with panes for playlists, search and the queue",
+    ))
+    .subcommand(
+        clap::Command::new("lastfm-login").about(
+            "get a last.fm session key for scrobbling, see [scrobble.lastfm] in config.toml",
+        ),
+    )
+}
+
+// what to do after parsing the command line
+pub enum Mode {
+    Repl,
+    // the full screen front end instead of the repl
+    Tui,
+    Once(Result<Command, ParseError>),
+    // with the rest api or not
+    Daemon { http: bool },
+    // the words of the command to send to the daemon
+    Ctl(Vec<String>),
+    LastFmLogin,
+}
+
+pub fn mode(matches: &ArgMatches) -> Mode {
+    match matches.subcommand() {
+        None => Mode::Repl,
+        Some(("daemon", args)) => Mode::Daemon {
+            http: args.get_flag("http"),
+        },
+        Some(("ctl", args)) => Mode::Ctl(
+            args.get_many::<String>("command")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+        ),
+        Some(("lastfm-login", _)) => Mode::LastFmLogin,
+        Some(("tui", _)) => Mode::Tui,
+        Some(_) => match command(matches) {
+            Some(command) => Mode::Once(command),
+            None => Mode::Repl,
+        },
+    }
+}
+
+pub fn output(matches: &ArgMatches) -> Output {
+    match matches.get_one::<String>("output").map(String::as_str) {
+        Some("json") => Output::Json,
+        _ => Output::Text,
+    }
+}
+
+// --pkce and --read-only, they go on top of what the profile says
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LoginFlags {
+    pub pkce: bool,
+    pub read_only: bool,
+}
+
+pub fn login_flags(matches: &ArgMatches) -> LoginFlags {
+    LoginFlags {
+        pkce: matches.get_flag("pkce"),
+        read_only: matches.get_flag("read-only"),
+    }
+}
+
+// turns `terminal-spotify play "album:Discovery"` into the same command the
+// repl gets from `play "album:Discovery"`
+fn command(matches: &ArgMatches) -> Option<Result<Command, ParseError>> {
+    let (name, args) = matches.subcommand()?;
+
+    let mut words = vec![name.to_string()];
+    if let Ok(Some(values)) = args.try_get_many::<String>("args") {
+        words.extend(values.cloned());
+    }
+    for (option, value) in options(find_command(name)?.args).unwrap_or_default() {
+        match value {
+            None if args.get_flag(option) => words.push(format!("--{}", option)),
+            None => (),
+            Some(_) => {
+                if let Some(value) = args.get_one::<String>(option) {
+                    words.push(format!("--{}", option));
+                    words.push(value.clone());
+                }
+            }
+        }
+    }
+
+    Some(parse(&shell_words::join(words)))
+}
+
+// `[--json] [--format <template>]` -> [("json", None), ("format", Some("template"))],
+// None if the arguments aren't all options
+fn options(args: &str) -> Option<Vec<(&str, Option<&str>)>> {
+    args.strip_prefix('[')?
+        .strip_suffix(']')?
+        .split("] [")
+        .map(|option| {
+            let option = option.strip_prefix("--")?;
+            Some(match option.split_once(' ') {
+                Some((name, value)) => (name, Some(value.trim_matches(['<', '>']))),
+                None => (option, None),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,947 @@
+use colored::Colorize;
+use std::fmt;
+use terminal_spotify::{print_err, printf_err};
+
+///// COMMAND DEFINITIONS /////
+// everything the repl knows about a command, the parser, the help text and
+// the scope registry all read from here
+#[derive(Debug)]
+pub struct CommandDef {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub args: &'static str,
+    pub description: &'static str,
+    // listed under "If a device is active" in help
+    pub needs_device: bool,
+    // still available without a user login (--read-only)
+    pub read_only: bool,
+    // scopes the token needs for the command to work
+    pub scopes: &'static [&'static str],
+}
+
+pub const COMMANDS: &[CommandDef] = &[
+    CommandDef {
+        name: "help",
+        aliases: &[],
+        args: "",
+        description: "get a list of available commands",
+        needs_device: false,
+        read_only: true,
+        scopes: &[],
+    },
+    CommandDef {
+        name: "exit",
+        aliases: &[],
+        args: "",
+        description: "exit",
+        needs_device: false,
+        read_only: true,
+        scopes: &[],
+    },
+    CommandDef {
+        name: "devices",
+        aliases: &[],
+        args: "",
+        description: "list the available devices",
+        needs_device: false,
+        read_only: false,
+        scopes: &["user-read-playback-state"],
+    },
+    CommandDef {
+        name: "activate",
+        aliases: &[],
+        args: "[device]",
+        description: "select a device you want to activate",
+        needs_device: false,
+        read_only: false,
+        scopes: &["user-read-playback-state", "user-modify-playback-state"],
+    },
+    CommandDef {
+        name: "profile",
+        aliases: &["profiles"],
+        args: "[name]",
+        description: "switch to another profile",
+        needs_device: false,
+        read_only: true,
+        scopes: &[],
+    },
+    CommandDef {
+        name: "whoami",
+        aliases: &[],
+        args: "",
+        description: "show who is logged in and the granted scopes",
+        needs_device: false,
+        read_only: false,
+        // country and subscription are only shown with user-read-private
+        scopes: &["user-read-private"],
+    },
+    CommandDef {
+        name: "logout",
+        aliases: &[],
+        args: "[--all]",
+        description:
+            "log out of the current profile, --all logs out of every profile and clears the keyring",
+        needs_device: false,
+        read_only: false,
+        scopes: &[],
+    },
+    CommandDef {
+        name: "credentials",
+        aliases: &[],
+        args: "set|show|clear",
+        description: "manage the client secret and refresh token stored in the keyring",
+        needs_device: false,
+        read_only: true,
+        scopes: &[],
+    },
+    CommandDef {
+        name: "song",
+        aliases: &["s", "search"],
+        args: "[query]",
+        description: "search for and play a song",
+        needs_device: true,
+        read_only: true,
+        scopes: &["user-modify-playback-state"],
+    },
+    CommandDef {
+        name: "album",
+        aliases: &["a"],
+        args: "[query]",
+        description: "search for and play an album",
+        needs_device: true,
+        read_only: true,
+        scopes: &["user-modify-playback-state"],
+    },
+    CommandDef {
+        name: "playlist",
+        aliases: &["playlists"],
+        args: "[name]",
+        description: "search for and play a personal playlist",
+        needs_device: true,
+        read_only: true,
+        scopes: &["playlist-read-private", "user-modify-playback-state"],
+    },
+    CommandDef {
+        name: "p",
+        aliases: &[],
+        args: "",
+        description: "resumes or pauses track, depending on which one is possible",
+        needs_device: true,
+        read_only: false,
+        scopes: &["user-read-currently-playing", "user-modify-playback-state"],
+    },
+    CommandDef {
+        name: "play",
+        aliases: &[],
+        args: "[query|album:query|playlist:name|spotify:uri]",
+        description: "resume playback, or search for a song, album or playlist and play it",
+        needs_device: true,
+        read_only: false,
+        scopes: &["user-read-currently-playing", "user-modify-playback-state"],
+    },
+    CommandDef {
+        name: "pause",
+        aliases: &[],
+        args: "",
+        description: "pause playback",
+        needs_device: true,
+        read_only: false,
+        scopes: &["user-read-currently-playing", "user-modify-playback-state"],
+    },
+    CommandDef {
+        name: "restart",
+        aliases: &["r"],
+        args: "",
+        description: "restarts track",
+        needs_device: true,
+        read_only: false,
+        scopes: &["user-read-currently-playing", "user-modify-playback-state"],
+    },
+    CommandDef {
+        name: "next",
+        aliases: &[],
+        args: "",
+        description: "skips to next track",
+        needs_device: true,
+        read_only: false,
+        scopes: &["user-read-currently-playing", "user-modify-playback-state"],
+    },
+    CommandDef {
+        name: "prev",
+        aliases: &["previous"],
+        args: "",
+        description: "skips to previous track",
+        needs_device: true,
+        read_only: false,
+        scopes: &["user-read-currently-playing", "user-modify-playback-state"],
+    },
+    CommandDef {
+        name: "forward",
+        aliases: &["fwd"],
+        args: "[seconds]",
+        description: "go forward in the track, asks for the amount of seconds if not given",
+        needs_device: true,
+        read_only: false,
+        scopes: &["user-read-currently-playing", "user-modify-playback-state"],
+    },
+    CommandDef {
+        name: "back",
+        aliases: &[],
+        args: "[seconds]",
+        description: "go back in the track, asks for the amount of seconds if not given",
+        needs_device: true,
+        read_only: false,
+        scopes: &["user-read-currently-playing", "user-modify-playback-state"],
+    },
+    CommandDef {
+        name: "seek",
+        aliases: &[],
+        args: "<m:ss|seconds>",
+        description: "go to a position in the track",
+        needs_device: true,
+        read_only: false,
+        scopes: &["user-modify-playback-state"],
+    },
+    CommandDef {
+        name: "volume",
+        aliases: &["vol"],
+        args: "<0-100>",
+        description: "set the volume of the active device",
+        needs_device: true,
+        read_only: false,
+        scopes: &["user-modify-playback-state"],
+    },
+    CommandDef {
+        name: "shuffle",
+        aliases: &[],
+        args: "[on|off]",
+        description: "turn shuffle on or off, without on/off it is switched",
+        needs_device: true,
+        read_only: false,
+        scopes: &["user-read-playback-state", "user-modify-playback-state"],
+    },
+    CommandDef {
+        name: "queue",
+        aliases: &["q"],
+        args: "[query]",
+        description: "show what is up next, or search for a song and add it to the queue",
+        needs_device: true,
+        read_only: false,
+        scopes: &[
+            "user-read-currently-playing",
+            "user-read-playback-state",
+            "user-modify-playback-state",
+        ],
+    },
+    CommandDef {
+        name: "status",
+        aliases: &[],
+        args: "[--json] [--format <template>] [--follow]",
+        description:
+            "get status of currently selected song, --follow prints a line every time it changes",
+        needs_device: true,
+        read_only: false,
+        scopes: &["user-read-currently-playing", "user-read-playback-state"],
+    },
+    CommandDef {
+        name: "keys",
+        aliases: &["hotkeys"],
+        args: "",
+        description: "control playback with single keys, space pauses, n/b skip, arrows seek, +/- volume, q stops",
+        needs_device: true,
+        read_only: false,
+        scopes: &[
+            "user-read-currently-playing",
+            "user-read-playback-state",
+            "user-modify-playback-state",
+        ],
+    },
+    CommandDef {
+        name: "watch",
+        aliases: &["w"],
+        args: "",
+        description: "show what is playing and up next until a key is pressed",
+        needs_device: false,
+        read_only: false,
+        scopes: &["user-read-currently-playing", "user-read-playback-state"],
+    },
+];
+
+pub fn find_command(name: &str) -> Option<&'static CommandDef> {
+    COMMANDS
+        .iter()
+        .find(|def| def.name == name || def.aliases.contains(&name))
+}
+
+impl CommandDef {
+    // s/search/song [query]
+    pub fn usage(&self) -> String {
+        let mut names: Vec<&str> = self.aliases.to_vec();
+        names.push(self.name);
+
+        if self.args.is_empty() {
+            return names.join("/");
+        }
+        format!("{} {}", names.join("/"), self.args)
+    }
+}
+
+///// COMMANDS /////
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Help,
+    Exit,
+    Devices,
+    Activate(Option<String>),
+    Profile(Option<String>),
+    Whoami,
+    Logout {
+        all: bool,
+    },
+    Credentials(CredentialsAction),
+    Song(Option<String>),
+    Album(Option<String>),
+    Playlist(Option<String>),
+    Toggle,
+    Play,
+    Pause,
+    Restart,
+    Next,
+    Prev,
+    Forward(Option<i64>),
+    Back(Option<i64>),
+    Seek(i64),
+    Volume(u8),
+    // None switches it
+    Shuffle(Option<bool>),
+    Queue(Option<String>),
+    // play spotify:album:.. [spotify:track:..], the track it starts from
+    PlayUri {
+        uri: String,
+        offset: Option<String>,
+    },
+    // format is a template for status bars, see template.rs
+    Status {
+        json: bool,
+        format: Option<String>,
+        follow: bool,
+    },
+    Watch,
+    Keys,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CredentialsAction {
+    Set,
+    Show,
+    Clear,
+}
+
+impl Command {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Help => "help",
+            Command::Exit => "exit",
+            Command::Devices => "devices",
+            Command::Activate(_) => "activate",
+            Command::Profile(_) => "profile",
+            Command::Whoami => "whoami",
+            Command::Logout { .. } => "logout",
+            Command::Credentials(_) => "credentials",
+            Command::Song(_) => "song",
+            Command::Album(_) => "album",
+            Command::Playlist(_) => "playlist",
+            Command::Toggle => "p",
+            Command::Play | Command::PlayUri { .. } => "play",
+            Command::Pause => "pause",
+            Command::Restart => "restart",
+            Command::Next => "next",
+            Command::Prev => "prev",
+            Command::Forward(_) => "forward",
+            Command::Back(_) => "back",
+            Command::Seek(_) => "seek",
+            Command::Volume(_) => "volume",
+            Command::Shuffle(_) => "shuffle",
+            Command::Queue(_) => "queue",
+            Command::Status { .. } => "status",
+            Command::Watch => "watch",
+            Command::Keys => "keys",
+        }
+    }
+
+    pub fn def(&self) -> &'static CommandDef {
+        find_command(self.name()).expect("every command has a definition")
+    }
+
+    pub fn scopes(&self) -> &'static [&'static str] {
+        self.def().scopes
+    }
+}
+
+///// RESULTS /////
+// what a handler hands back to the repl or the cli to show
+#[derive(Debug)]
+pub enum Reply {
+    Done,
+    Message(String),
+    // a message for people and the same thing for --output json
+    Data {
+        text: String,
+        data: serde_json::Value,
+    },
+    // ends the session, with something to say before it does
+    Exit(Option<String>),
+    SwitchProfile(String),
+}
+
+impl Reply {
+    pub fn data(text: String, data: impl serde::Serialize) -> Reply {
+        Reply::Data {
+            text,
+            data: serde_json::to_value(data).unwrap_or_default(),
+        }
+    }
+}
+
+// every error maps to its own exit code so scripts can tell them apart
+#[derive(Debug)]
+pub enum CommandError {
+    // a spotify request or anything else the command depends on failed
+    Failed(String),
+    Usage(String),
+    NoDevice(&'static str),
+    NotFound(String),
+    // the command would have to ask something but there is no terminal
+    NeedsInput(&'static str),
+    MissingScopes(Vec<&'static str>),
+}
+
+impl CommandError {
+    pub fn failed<T: fmt::Display>(text: &str, err: T) -> CommandError {
+        CommandError::Failed(format!("{}: {}", text, err))
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CommandError::Failed(_) => 1,
+            CommandError::Usage(_) => 2,
+            CommandError::NoDevice(_) => 3,
+            CommandError::NotFound(_) => 4,
+            CommandError::NeedsInput(_) => 5,
+            CommandError::MissingScopes(_) => 6,
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Failed(err) | CommandError::Usage(err) => write!(f, "{}", err),
+            CommandError::NoDevice(text) => write!(f, "{} because there is no active device", text),
+            CommandError::NotFound(text) => write!(f, "{}", text),
+            CommandError::NeedsInput(text) => {
+                write!(f, "{}, there is no terminal to ask in", text)
+            }
+            CommandError::MissingScopes(scopes) => write!(
+                f,
+                "This command needs permissions you have not granted: {}",
+                scopes.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl From<ParseError> for CommandError {
+    fn from(err: ParseError) -> Self {
+        CommandError::Usage(err.to_string())
+    }
+}
+
+///// PARSING /////
+// no track is longer than this, anything more is a typo
+pub const MAX_SEEK_SECONDS: i64 = 24 * 60 * 60;
+
+#[derive(Debug)]
+pub enum ParseError {
+    Empty,
+    UnclosedQuote,
+    UnknownCommand(String),
+    Usage(&'static CommandDef),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "No command given"),
+            ParseError::UnclosedQuote => write!(f, "Missing closing quote"),
+            ParseError::UnknownCommand(name) => write!(f, "Command not found: {}", name),
+            ParseError::Usage(def) => write!(f, "Usage: {}", def.usage()),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// splits like a shell so `s "never gonna"` and `s never gonna` both work
+pub fn parse(input: &str) -> Result<Command, ParseError> {
+    let words = shell_words::split(input).map_err(|_| ParseError::UnclosedQuote)?;
+
+    let (name, args) = match words.split_first() {
+        Some(split) => split,
+        None => return Err(ParseError::Empty),
+    };
+
+    let def = find_command(name).ok_or_else(|| ParseError::UnknownCommand(name.clone()))?;
+    let usage = || ParseError::Usage(def);
+
+    // free text arguments, ex: a search query or a device name
+    let text = if args.is_empty() {
+        None
+    } else {
+        Some(args.join(" "))
+    };
+
+    let command = match def.name {
+        "activate" => Command::Activate(text),
+        "profile" => Command::Profile(text),
+        "song" => Command::Song(text),
+        "album" => Command::Album(text),
+        "playlist" => Command::Playlist(text),
+        // play "album:Discovery", a plain query is a song
+        "play" => match text {
+            None => Command::Play,
+            Some(_) if args[0].starts_with("spotify:") => match args {
+                [uri] => Command::PlayUri {
+                    uri: uri.clone(),
+                    offset: None,
+                },
+                [uri, offset] => Command::PlayUri {
+                    uri: uri.clone(),
+                    offset: Some(offset.clone()),
+                },
+                _ => return Err(usage()),
+            },
+            Some(text) => match text.split_once(':') {
+                // play album: with nothing after it
+                Some(("album" | "playlist" | "song" | "track", query))
+                    if query.trim().is_empty() =>
+                {
+                    return Err(usage())
+                }
+                Some(("album", query)) => Command::Album(Some(query.trim().to_string())),
+                Some(("playlist", name)) => Command::Playlist(Some(name.trim().to_string())),
+                Some(("song" | "track", query)) => Command::Song(Some(query.trim().to_string())),
+                _ => Command::Song(Some(text)),
+            },
+        },
+        "status" => parse_status(args).ok_or_else(usage)?,
+        "forward" => Command::Forward(parse_seconds(args).ok_or_else(usage)?),
+        "back" => Command::Back(parse_seconds(args).ok_or_else(usage)?),
+        "seek" => match args {
+            [position] => Command::Seek(parse_position(position).ok_or_else(usage)?),
+            _ => return Err(usage()),
+        },
+        "volume" => match args {
+            [percent] => match percent.trim_end_matches('%').parse::<u8>() {
+                Ok(percent) if percent <= 100 => Command::Volume(percent),
+                _ => return Err(usage()),
+            },
+            _ => return Err(usage()),
+        },
+        "shuffle" => match args {
+            [] => Command::Shuffle(None),
+            [state] if state == "on" => Command::Shuffle(Some(true)),
+            [state] if state == "off" => Command::Shuffle(Some(false)),
+            _ => return Err(usage()),
+        },
+        "queue" => Command::Queue(text),
+        "logout" => match args {
+            [] => Command::Logout { all: false },
+            [flag] if flag == "--all" => Command::Logout { all: true },
+            _ => return Err(usage()),
+        },
+        "credentials" => match args {
+            [action] if action == "set" => Command::Credentials(CredentialsAction::Set),
+            [action] if action == "show" => Command::Credentials(CredentialsAction::Show),
+            [action] if action == "clear" => Command::Credentials(CredentialsAction::Clear),
+            _ => return Err(usage()),
+        },
+        // the rest don't take arguments
+        _ if !args.is_empty() => return Err(usage()),
+        "help" => Command::Help,
+        "exit" => Command::Exit,
+        "devices" => Command::Devices,
+        "whoami" => Command::Whoami,
+        "p" => Command::Toggle,
+        "pause" => Command::Pause,
+        "restart" => Command::Restart,
+        "next" => Command::Next,
+        "prev" => Command::Prev,
+        "watch" => Command::Watch,
+        "keys" => Command::Keys,
+        _ => return Err(ParseError::UnknownCommand(name.clone())),
+    };
+
+    Ok(command)
+}
+
+// Some(None) when no seconds were given, None if they are not a number
+fn parse_seconds(args: &[String]) -> Option<Option<i64>> {
+    match args {
+        [] => Some(None),
+        [seconds] => match seconds.parse::<i64>() {
+            Ok(seconds) if (1..=MAX_SEEK_SECONDS).contains(&seconds) => Some(Some(seconds)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// --json and --format can't be used together
+fn parse_status(args: &[String]) -> Option<Command> {
+    let (mut json, mut format, mut follow) = (false, None, false);
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--follow" => follow = true,
+            "--format" => format = Some(args.next()?.clone()),
+            _ => return None,
+        }
+    }
+
+    if json && format.is_some() {
+        return None;
+    }
+    Some(Command::Status {
+        json,
+        format,
+        follow,
+    })
+}
+
+// 1:30 or 90, in seconds
+fn parse_position(position: &str) -> Option<i64> {
+    let seconds = match position.split_once(':') {
+        Some((minutes, seconds)) => {
+            let minutes = minutes.parse::<i64>().ok()?;
+            let seconds = seconds.parse::<i64>().ok()?;
+            if minutes < 0 || !(0..60).contains(&seconds) {
+                return None;
+            }
+            minutes.checked_mul(60)?.checked_add(seconds)?
+        }
+        None => position.parse::<i64>().ok()?,
+    };
+
+    if !(0..=MAX_SEEK_SECONDS).contains(&seconds) {
+        return None;
+    }
+    Some(seconds)
+}
+
+// parses a line from the repl and prints what was wrong with it
+pub fn parse_input(input: &str) -> Option<Command> {
+    match parse(input) {
+        Ok(command) => Some(command),
+        Err(ParseError::Empty) => None,
+        Err(ParseError::UnknownCommand(name)) => {
+            printf_err("Command not found", name);
+            None
+        }
+        Err(err) => {
+            print_err(&err.to_string());
+            None
+        }
+    }
+}
+
+///// HELP /////
+pub fn help_text(read_only: bool) -> String {
+    let line = |def: &CommandDef| format!("{} -> {}\n", def.usage(), def.description);
+
+    let mut help = String::from("Available commands:\n\n");
+
+    if read_only {
+        help += &format!("{}\n", "Read-only mode".bold().yellow());
+        for def in COMMANDS.iter().filter(|def| def.read_only) {
+            help += &line(def);
+        }
+        help += "\nsongs, albums and playlists are shown instead of played";
+        return help;
+    }
+
+    help += &format!("{}\n", "Always available".bold().yellow());
+    for def in COMMANDS.iter().filter(|def| !def.needs_device) {
+        help += &line(def);
+    }
+
+    help += &format!("\n{}\n", "If a device is active:".bold().green());
+    for def in COMMANDS.iter().filter(|def| def.needs_device) {
+        help += &line(def);
+    }
+
+    help.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn seconds_default_when_left_out() {
+        assert_eq!(parse_seconds(&[]), Some(None));
+        assert_eq!(parse_seconds(&words(&["30"])), Some(Some(30)));
+    }
+
+    #[test]
+    fn seconds_bounds() {
+        assert_eq!(parse_seconds(&words(&["1"])), Some(Some(1)));
+        assert_eq!(
+            parse_seconds(&words(&[&MAX_SEEK_SECONDS.to_string()])),
+            Some(Some(MAX_SEEK_SECONDS))
+        );
+        assert_eq!(parse_seconds(&words(&["0"])), None);
+        assert_eq!(parse_seconds(&words(&["-5"])), None);
+        assert_eq!(
+            parse_seconds(&words(&[&(MAX_SEEK_SECONDS + 1).to_string()])),
+            None
+        );
+        assert_eq!(parse_seconds(&words(&["9223372036854775807"])), None);
+        assert_eq!(parse_seconds(&words(&["99999999999999999999"])), None);
+    }
+
+    #[test]
+    fn seconds_rejects_anything_else() {
+        assert_eq!(parse_seconds(&words(&["ten"])), None);
+        assert_eq!(parse_seconds(&words(&["1.5"])), None);
+        assert_eq!(parse_seconds(&words(&["10", "20"])), None);
+    }
+
+    #[test]
+    fn position_in_seconds() {
+        assert_eq!(parse_position("0"), Some(0));
+        assert_eq!(parse_position("90"), Some(90));
+        assert_eq!(
+            parse_position(&MAX_SEEK_SECONDS.to_string()),
+            Some(MAX_SEEK_SECONDS)
+        );
+        assert_eq!(parse_position(&(MAX_SEEK_SECONDS + 1).to_string()), None);
+        assert_eq!(parse_position("-1"), None);
+        assert_eq!(parse_position(""), None);
+        assert_eq!(parse_position("abc"), None);
+    }
+
+    #[test]
+    fn position_in_minutes_and_seconds() {
+        assert_eq!(parse_position("1:30"), Some(90));
+        assert_eq!(parse_position("0:00"), Some(0));
+        assert_eq!(parse_position("0:59"), Some(59));
+        assert_eq!(parse_position("12:05"), Some(725));
+        assert_eq!(parse_position("1440:00"), Some(MAX_SEEK_SECONDS));
+    }
+
+    #[test]
+    fn position_minutes_and_seconds_bounds() {
+        assert_eq!(parse_position("0:60"), None);
+        assert_eq!(parse_position("1:-1"), None);
+        assert_eq!(parse_position("-1:30"), None);
+        assert_eq!(parse_position("1440:01"), None);
+        assert_eq!(parse_position("1:"), None);
+        assert_eq!(parse_position(":30"), None);
+        assert_eq!(parse_position("1:2:3"), None);
+    }
+
+    #[test]
+    fn position_does_not_overflow() {
+        assert_eq!(parse_position("9223372036854775807:00"), None);
+        assert_eq!(parse_position("153722867280912930:59"), None);
+        assert_eq!(parse_position("99999999999999999999"), None);
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(
+            parse(r#"s "never gonna give you up""#).unwrap(),
+            Command::Song(Some(String::from("never gonna give you up")))
+        );
+        // the words are joined with single spaces
+        assert_eq!(
+            parse("song  never   gonna").unwrap(),
+            Command::Song(Some(String::from("never gonna")))
+        );
+        assert_eq!(
+            parse(r#"activate 'Kitchen speaker'"#).unwrap(),
+            Command::Activate(Some(String::from("Kitchen speaker")))
+        );
+        assert_eq!(
+            parse(r#"song it\'s"#).unwrap(),
+            Command::Song(Some(String::from("it's")))
+        );
+        assert!(matches!(
+            parse(r#"song "never"#),
+            Err(ParseError::UnclosedQuote)
+        ));
+    }
+
+    #[test]
+    fn empty_and_unknown() {
+        assert!(matches!(parse(""), Err(ParseError::Empty)));
+        assert!(matches!(parse("   "), Err(ParseError::Empty)));
+        assert!(matches!(
+            parse("dance"),
+            Err(ParseError::UnknownCommand(name)) if name == "dance"
+        ));
+    }
+
+    #[test]
+    fn play_prefixes() {
+        assert_eq!(parse("play").unwrap(), Command::Play);
+        assert_eq!(
+            parse(r#"play "album:Discovery""#).unwrap(),
+            Command::Album(Some(String::from("Discovery")))
+        );
+        assert_eq!(
+            parse("play album: Random Access Memories").unwrap(),
+            Command::Album(Some(String::from("Random Access Memories")))
+        );
+        assert_eq!(
+            parse(r#"play "playlist:Lo-fi Focus""#).unwrap(),
+            Command::Playlist(Some(String::from("Lo-fi Focus")))
+        );
+        assert_eq!(
+            parse("play song:One More Time").unwrap(),
+            Command::Song(Some(String::from("One More Time")))
+        );
+        assert_eq!(
+            parse("play track:Aerodynamic").unwrap(),
+            Command::Song(Some(String::from("Aerodynamic")))
+        );
+        // anything else is a song, colons included
+        assert_eq!(
+            parse("play one more time").unwrap(),
+            Command::Song(Some(String::from("one more time")))
+        );
+        assert_eq!(
+            parse("play artist:Daft Punk").unwrap(),
+            Command::Song(Some(String::from("artist:Daft Punk")))
+        );
+    }
+
+    #[test]
+    fn play_prefixes_need_a_query() {
+        for input in [
+            "play album:",
+            "play playlist:",
+            "play song:",
+            "play 'track:  '",
+        ] {
+            assert!(
+                matches!(parse(input), Err(ParseError::Usage(def)) if def.name == "play"),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn play_uris() {
+        assert_eq!(
+            parse("play spotify:album:4m2880jivSbbyEGAKfITCa").unwrap(),
+            Command::PlayUri {
+                uri: String::from("spotify:album:4m2880jivSbbyEGAKfITCa"),
+                offset: None
+            }
+        );
+        assert_eq!(
+            parse("play spotify:album:4m2880jivSbbyEGAKfITCa 3").unwrap(),
+            Command::PlayUri {
+                uri: String::from("spotify:album:4m2880jivSbbyEGAKfITCa"),
+                offset: Some(String::from("3"))
+            }
+        );
+        assert!(matches!(
+            parse("play spotify:album:x 3 4"),
+            Err(ParseError::Usage(_))
+        ));
+    }
+
+    #[test]
+    fn usage_errors() {
+        for input in [
+            "volume",
+            "volume 101",
+            "volume -1",
+            "volume loud",
+            "volume 10 20",
+            "shuffle maybe",
+            "shuffle on off",
+            "logout --everything",
+            "credentials",
+            "credentials dump",
+            "next 2",
+            "pause now",
+            "status --json --format {title}",
+            "status --format",
+            "status --verbose",
+        ] {
+            assert!(
+                matches!(parse(input), Err(ParseError::Usage(_))),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn usage_error_names_the_command() {
+        match parse("volume 101") {
+            Err(err @ ParseError::Usage(def)) => {
+                assert_eq!(def.name, "volume");
+                assert!(err.to_string().contains("volume"), "{}", err);
+            }
+            res => panic!("expected a usage error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn arguments() {
+        assert_eq!(parse("volume 40%").unwrap(), Command::Volume(40));
+        assert_eq!(parse("shuffle on").unwrap(), Command::Shuffle(Some(true)));
+        assert_eq!(parse("shuffle").unwrap(), Command::Shuffle(None));
+        assert_eq!(
+            parse("logout --all").unwrap(),
+            Command::Logout { all: true }
+        );
+        assert_eq!(
+            parse("status --format {title}").unwrap(),
+            Command::Status {
+                json: false,
+                format: Some(String::from("{title}")),
+                follow: false
+            }
+        );
+    }
+
+    #[test]
+    fn seek_commands() {
+        assert_eq!(parse("fwd").unwrap(), Command::Forward(None));
+        assert_eq!(parse("forward 15").unwrap(), Command::Forward(Some(15)));
+        assert_eq!(parse("back 5").unwrap(), Command::Back(Some(5)));
+        assert_eq!(parse("seek 1:30").unwrap(), Command::Seek(90));
+        assert!(matches!(parse("seek"), Err(ParseError::Usage(_))));
+        assert!(matches!(parse("seek 0:75"), Err(ParseError::Usage(_))));
+        assert!(matches!(parse("forward 0"), Err(ParseError::Usage(_))));
+        assert!(matches!(
+            parse("back 99999999999"),
+            Err(ParseError::Usage(_))
+        ));
+    }
+}
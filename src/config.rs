@@ -0,0 +1,517 @@
+use serde::Deserialize;
+use std::{collections::HashMap, env, fs, path::PathBuf, time::Duration};
+use terminal_spotify::get_env;
+
+use crate::{auth::REDIRECT_URI, command::MAX_SEEK_SECONDS};
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+const CONFIG_FILE: &str = "config.toml";
+
+// ~/.config/terminal-spotify (or $XDG_CONFIG_HOME/terminal-spotify)
+pub fn config_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("terminal-spotify")
+}
+
+pub fn profiles_dir() -> PathBuf {
+    config_dir().join("profiles")
+}
+
+// every profile keeps its token cache etc in its own folder
+pub fn profile_dir(profile: &str) -> PathBuf {
+    profiles_dir().join(profile)
+}
+
+// the name ends up in paths, so ../x or a/b would reach outside the profiles
+pub fn check_profile_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") {
+        return Err(format!(
+            "Invalid profile name: {}, it can't be empty or contain /, \\ or ..",
+            name
+        ));
+    }
+    Ok(())
+}
+
+// where the daemon listens, in $XDG_RUNTIME_DIR so it's gone after a reboot
+pub fn socket_path(profile: &str) -> PathBuf {
+    match dirs::runtime_dir() {
+        Some(dir) => dir
+            .join("terminal-spotify")
+            .join(format!("{}.sock", profile)),
+        None => profile_dir(profile).join("daemon.sock"),
+    }
+}
+
+///// CONFIG FILE /////
+// ~/.config/terminal-spotify/config.toml, ex:
+//
+// [profiles.work]
+// client_id = "..."
+// pkce = true
+// default_device = "Work laptop"
+// [profiles.work.aliases]
+// focus = 'playlist "Deep Work"'
+//
+// [http]
+// enabled = true
+//
+// [events]
+// poll_interval_ms = 1000
+//
+// [mpris]
+// enabled = false
+//
+// [notifications]
+// enabled = true
+// urgency = "low"
+//
+// [scrobble.listenbrainz]
+// token = "..."
+//
+// [editor]
+// mode = "vi"
+//
+// [keys]
+// seek_step = 15
+// [keys.bindings]
+// s = "playlist \"Lo-fi Focus\""
+//
+// [aliases]
+// chill = 'playlist "Lo-fi Focus" ; shuffle on ; volume 40'
+#[derive(Debug, Default, Deserialize)]
+pub struct AppConfig {
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub events: EventsConfig,
+    #[serde(default)]
+    pub mpris: MprisConfig,
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+    #[serde(default)]
+    pub scrobble: ScrobbleConfig,
+    #[serde(default)]
+    pub keys: KeysConfig,
+    #[serde(default)]
+    pub editor: EditorConfig,
+    // name -> commands, see alias.rs
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+}
+
+// the rest api the daemon can serve
+#[derive(Debug, Clone, Deserialize)]
+pub struct HttpConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // only reachable from this machine unless changed
+    #[serde(default = "default_http_address")]
+    pub address: String,
+    // a token is generated and saved next to the config if there is none
+    pub token: Option<String>,
+}
+
+fn default_http_address() -> String {
+    String::from("127.0.0.1:8910")
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            enabled: false,
+            address: default_http_address(),
+            token: None,
+        }
+    }
+}
+
+// how often the daemon asks spotify for the player state to send events
+#[derive(Debug, Clone, Deserialize)]
+pub struct EventsConfig {
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval_ms: u64,
+    // the interval doubles up to this while nothing changes and nothing plays
+    #[serde(default = "default_idle_poll_interval")]
+    pub idle_poll_interval_ms: u64,
+}
+
+impl EventsConfig {
+    // the poll interval and how far it may back off to
+    pub fn intervals(&self) -> (Duration, Duration) {
+        let poll_interval = Duration::from_millis(self.poll_interval_ms.max(100));
+        let idle_interval = Duration::from_millis(self.idle_poll_interval_ms).max(poll_interval);
+        (poll_interval, idle_interval)
+    }
+}
+
+fn default_poll_interval() -> u64 {
+    1000
+}
+
+fn default_idle_poll_interval() -> u64 {
+    15000
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        EventsConfig {
+            poll_interval_ms: default_poll_interval(),
+            idle_poll_interval_ms: default_idle_poll_interval(),
+        }
+    }
+}
+
+// the media player the daemon shows on the session bus for media keys
+#[derive(Debug, Clone, Deserialize)]
+pub struct MprisConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for MprisConfig {
+    fn default() -> Self {
+        MprisConfig { enabled: true }
+    }
+}
+
+// a desktop notification every time the track changes
+#[derive(Debug, Clone, Deserialize)]
+pub struct NotificationsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub urgency: Urgency,
+    #[serde(default = "default_true")]
+    pub cover_art: bool,
+    // not while the terminal the repl runs in is the focused window
+    #[serde(default = "default_true")]
+    pub suppress_when_focused: bool,
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        NotificationsConfig {
+            enabled: false,
+            urgency: Urgency::default(),
+            cover_art: true,
+            suppress_when_focused: true,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Urgency {
+    #[default]
+    Low,
+    Normal,
+    Critical,
+}
+
+// the line editor of the repl and the search prompts
+#[derive(Debug, Clone, Deserialize)]
+pub struct EditorConfig {
+    #[serde(default)]
+    pub mode: EditMode,
+    // lines kept per profile, for the commands and the searches each
+    #[serde(default = "default_history_size")]
+    pub history_size: usize,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EditMode {
+    #[default]
+    Emacs,
+    Vi,
+}
+
+fn default_history_size() -> usize {
+    1000
+}
+
+impl Default for EditorConfig {
+    fn default() -> Self {
+        EditorConfig {
+            mode: EditMode::default(),
+            history_size: default_history_size(),
+        }
+    }
+}
+
+// the single key mode, `keys` in the repl
+#[derive(Debug, Clone, Deserialize)]
+pub struct KeysConfig {
+    // seconds the arrow keys seek
+    #[serde(default = "default_seek_step")]
+    pub seek_step: i64,
+    // percent + and - change the volume by
+    #[serde(default = "default_volume_step")]
+    pub volume_step: u8,
+    // key -> action, on top of the default ones. an action is seek_forward,
+    // seek_back, volume_up, volume_down, quit or any repl command. keys are a
+    // character or space, enter, tab, esc, left, right, up, down
+    #[serde(default)]
+    pub bindings: HashMap<String, String>,
+}
+
+const DEFAULT_BINDINGS: &[(&str, &str)] = &[
+    ("space", "p"),
+    ("n", "next"),
+    ("b", "prev"),
+    ("right", "seek_forward"),
+    ("left", "seek_back"),
+    ("+", "volume_up"),
+    ("=", "volume_up"),
+    ("-", "volume_down"),
+    ("/", "song"),
+    ("q", "quit"),
+    ("esc", "quit"),
+];
+
+impl KeysConfig {
+    // the default bindings with the ones from the config over them
+    pub fn keymap(&self) -> HashMap<String, String> {
+        let mut keymap: HashMap<String, String> = DEFAULT_BINDINGS
+            .iter()
+            .map(|(key, action)| (key.to_string(), action.to_string()))
+            .collect();
+        keymap.extend(self.bindings.clone());
+        keymap
+    }
+}
+
+fn default_seek_step() -> i64 {
+    10
+}
+
+fn default_volume_step() -> u8 {
+    5
+}
+
+impl Default for KeysConfig {
+    fn default() -> Self {
+        KeysConfig {
+            seek_step: default_seek_step(),
+            volume_step: default_volume_step(),
+            bindings: HashMap::new(),
+        }
+    }
+}
+
+// where to scrobble to, a service without credentials is left out
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct ScrobbleConfig {
+    pub lastfm: Option<LastFmConfig>,
+    pub listenbrainz: Option<ListenBrainzConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LastFmConfig {
+    pub api_key: String,
+    pub api_secret: String,
+    // from `terminal-spotify lastfm-login`
+    pub session_key: Option<String>,
+    #[serde(default = "default_lastfm_url")]
+    pub url: String,
+    // where lastfm-login sends the browser to allow scrobbling
+    #[serde(default = "default_lastfm_auth_url")]
+    pub auth_url: String,
+}
+
+fn default_lastfm_url() -> String {
+    String::from("https://ws.audioscrobbler.com/2.0/")
+}
+
+fn default_lastfm_auth_url() -> String {
+    String::from("https://www.last.fm/api/auth/")
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ListenBrainzConfig {
+    pub token: String,
+    #[serde(default = "default_listenbrainz_url")]
+    pub url: String,
+}
+
+fn default_listenbrainz_url() -> String {
+    String::from("https://api.listenbrainz.org")
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Profile {
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    // log in with pkce, then no client secret is needed
+    #[serde(default)]
+    pub pkce: bool,
+    // only search and browse, no user login (client credentials)
+    #[serde(default)]
+    pub read_only: bool,
+    // keep the refresh token in the keyring instead of the token cache file
+    #[serde(default)]
+    pub keyring: bool,
+    // device name to activate on startup if nothing is playing anywhere
+    pub default_device: Option<String>,
+    // the redirect uri registered for the profile's client id
+    pub redirect_uri: Option<String>,
+    // used instead of the [editor] and [keys] of the config
+    pub editor: Option<EditorConfig>,
+    pub keys: Option<KeysConfig>,
+    // on top of the [aliases] of the config
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+}
+
+impl AppConfig {
+    pub fn load() -> Result<AppConfig, Box<dyn std::error::Error>> {
+        let path = config_dir().join(CONFIG_FILE);
+        if !path.exists() {
+            return Ok(AppConfig::default());
+        }
+
+        let content = fs::read_to_string(&path)?;
+        let config: AppConfig = match toml::from_str(&content) {
+            Ok(config) => config,
+            Err(err) => return Err(format!("Could not parse {}: {}", path.display(), err).into()),
+        };
+        config
+            .check()
+            .map_err(|err| format!("Invalid {}: {}", path.display(), err))?;
+        Ok(config)
+    }
+
+    // what parses but can't be used, ex: a negative seek_step
+    fn check(&self) -> Result<(), String> {
+        let keys = self
+            .profiles
+            .iter()
+            .filter_map(|(name, profile)| {
+                Some((format!("[profiles.{}.keys]", name), profile.keys.as_ref()?))
+            })
+            .chain([(String::from("[keys]"), &self.keys)]);
+
+        for (section, keys) in keys {
+            if !(1..=MAX_SEEK_SECONDS).contains(&keys.seek_step) {
+                return Err(format!(
+                    "seek_step under {} has to be between 1 and {} seconds",
+                    section, MAX_SEEK_SECONDS
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // the default profile doesn't have to be in the config file
+    pub fn profile(&self, name: &str) -> Option<Profile> {
+        if check_profile_name(name).is_err() {
+            return None;
+        }
+        match self.profiles.get(name) {
+            Some(profile) => Some(profile.clone()),
+            None if name == DEFAULT_PROFILE => Some(Profile::default()),
+            None => None,
+        }
+    }
+
+    pub fn profile_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .profiles
+            .keys()
+            .filter(|name| check_profile_name(name).is_ok())
+            .cloned()
+            .collect();
+        if !names.iter().any(|name| name == DEFAULT_PROFILE) {
+            names.push(DEFAULT_PROFILE.to_string());
+        }
+        names.sort();
+        names
+    }
+
+    // the settings a profile can have its own of
+    pub fn editor(&self, profile: &str) -> EditorConfig {
+        match self.profiles.get(profile).and_then(|p| p.editor.clone()) {
+            Some(editor) => editor,
+            None => self.editor.clone(),
+        }
+    }
+
+    pub fn keys(&self, profile: &str) -> KeysConfig {
+        match self.profiles.get(profile).and_then(|p| p.keys.clone()) {
+            Some(keys) => keys,
+            None => self.keys.clone(),
+        }
+    }
+
+    pub fn aliases(&self, profile: &str) -> HashMap<String, String> {
+        let mut aliases = self.aliases.clone();
+        if let Some(profile) = self.profiles.get(profile) {
+            aliases.extend(profile.aliases.clone());
+        }
+        aliases
+    }
+}
+
+impl Profile {
+    pub fn redirect_uri(&self) -> String {
+        match &self.redirect_uri {
+            Some(uri) => uri.clone(),
+            None => REDIRECT_URI.to_string(),
+        }
+    }
+
+    // falls back to the env variables (.env) if it's not in the profile
+    pub fn client_id(&self) -> String {
+        match &self.client_id {
+            Some(id) => id.clone(),
+            None => get_env("RSPOTIFY_CLIENT_ID"),
+        }
+    }
+
+    // pkce can also be chosen with --pkce or RSPOTIFY_AUTH_FLOW=pkce in .env
+    pub fn use_pkce(&self, flag: bool) -> bool {
+        self.pkce || flag || env::var("RSPOTIFY_AUTH_FLOW").is_ok_and(|flow| flow == "pkce")
+    }
+
+    // read-only can also be chosen with --read-only or RSPOTIFY_AUTH_FLOW=read-only (for ci)
+    pub fn use_read_only(&self, flag: bool) -> bool {
+        self.read_only
+            || flag
+            || env::var("RSPOTIFY_AUTH_FLOW").is_ok_and(|flow| flow == "read-only")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(content: &str) -> Result<(), String> {
+        toml::from_str::<AppConfig>(content).unwrap().check()
+    }
+
+    #[test]
+    fn seek_step_bounds() {
+        assert!(check("").is_ok());
+        assert!(check("[keys]\nseek_step = 1").is_ok());
+        assert!(check(&format!("[keys]\nseek_step = {}", MAX_SEEK_SECONDS)).is_ok());
+        assert!(check("[keys]\nseek_step = 0").is_err());
+        assert!(check("[keys]\nseek_step = -10").is_err());
+        assert!(check(&format!("[keys]\nseek_step = {}", MAX_SEEK_SECONDS + 1)).is_err());
+    }
+
+    #[test]
+    fn seek_step_in_profiles() {
+        let err = check("[profiles.work.keys]\nseek_step = 0").unwrap_err();
+        assert!(err.contains("[profiles.work.keys]"), "{}", err);
+        assert!(check("[profiles.work.keys]\nseek_step = 30").is_ok());
+    }
+}
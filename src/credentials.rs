@@ -0,0 +1,190 @@
+use dialoguer::Password;
+use rspotify::Token;
+use secret_service::{EncryptionType, SecretService};
+use std::{collections::HashMap, env, time::Duration};
+use terminal_spotify::{can_prompt, get_env};
+use tokio::time::timeout;
+
+use crate::{
+    auth::token_cache_path,
+    command::{CommandError, CredentialsAction},
+    config::{AppConfig, Profile},
+};
+
+///// KEYRING /////
+// secrets are stored in the secret service (gnome-keyring, kwallet, keepassxc..)
+// and found again by their attributes
+const APPLICATION: &str = "terminal-spotify";
+
+pub const CLIENT_SECRET: &str = "client_secret";
+pub const REFRESH_TOKEN: &str = "refresh_token";
+
+// connecting can hang if the bus is there but no secret service answers
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn attributes<'a>(profile: &'a str, kind: &'a str) -> HashMap<&'a str, &'a str> {
+    HashMap::from([
+        ("application", APPLICATION),
+        ("profile", profile),
+        ("kind", kind),
+    ])
+}
+
+async fn connect() -> Result<SecretService<'static>, Box<dyn std::error::Error>> {
+    match timeout(CONNECT_TIMEOUT, SecretService::connect(EncryptionType::Dh)).await {
+        Ok(res) => Ok(res?),
+        Err(_) => Err("Timed out connecting to the secret service".into()),
+    }
+}
+
+pub async fn get_secret(
+    profile: &str,
+    kind: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let ss = connect().await?;
+    let items = ss.search_items(attributes(profile, kind)).await?;
+
+    let item = match (items.unlocked.first(), items.locked.first()) {
+        (Some(item), _) => item,
+        (None, Some(item)) => {
+            item.unlock().await?;
+            item
+        }
+        (None, None) => return Ok(None),
+    };
+
+    let secret = item.get_secret().await?;
+    Ok(Some(String::from_utf8(secret)?))
+}
+
+pub async fn set_secret(
+    profile: &str,
+    kind: &str,
+    secret: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let ss = connect().await?;
+    let collection = ss.get_any_collection().await?;
+    collection.ensure_unlocked().await?;
+
+    let label = format!("terminal-spotify {} ({})", kind, profile);
+    collection
+        .create_item(
+            &label,
+            attributes(profile, kind),
+            secret.as_bytes(),
+            true, // replace the old one
+            "text/plain",
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_secret(profile: &str, kind: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let ss = connect().await?;
+    let items = ss.search_items(attributes(profile, kind)).await?;
+
+    for item in items.unlocked.iter().chain(items.locked.iter()) {
+        item.delete().await?;
+    }
+
+    Ok(())
+}
+
+// every secret of every profile
+pub async fn delete_all_secrets() -> Result<(), Box<dyn std::error::Error>> {
+    let ss = connect().await?;
+    let items = ss
+        .search_items(HashMap::from([("application", APPLICATION)]))
+        .await?;
+
+    for item in items.unlocked.iter().chain(items.locked.iter()) {
+        item.delete().await?;
+    }
+
+    Ok(())
+}
+
+// the client secret from the profile, the keyring or .env in that order
+pub async fn client_secret(profile_name: &str, profile: &Profile) -> String {
+    if let Some(secret) = &profile.client_secret {
+        return secret.clone();
+    }
+
+    if let Ok(Some(secret)) = get_secret(profile_name, CLIENT_SECRET).await {
+        return secret;
+    }
+
+    get_env("RSPOTIFY_CLIENT_SECRET")
+}
+
+///// COMMANDS /////
+// credentials set/show/clear
+pub async fn credentials_command(
+    profile_name: &str,
+    action: CredentialsAction,
+) -> Result<String, CommandError> {
+    match action {
+        CredentialsAction::Set => {
+            if !can_prompt() {
+                return Err(CommandError::NeedsInput(
+                    "The client secret has to be typed in",
+                ));
+            }
+
+            let secret = Password::new()
+                .with_prompt(format!("Client secret for {}", profile_name))
+                .interact()
+                .unwrap();
+
+            set_secret(profile_name, CLIENT_SECRET, secret.trim())
+                .await
+                .map_err(|err| CommandError::failed("Could not store the client secret", err))?;
+            Ok("Client secret was stored in the keyring".to_string())
+        }
+        CredentialsAction::Show => show_credentials(profile_name).await,
+        CredentialsAction::Clear => {
+            for kind in [CLIENT_SECRET, REFRESH_TOKEN] {
+                delete_secret(profile_name, kind)
+                    .await
+                    .map_err(|err| CommandError::failed("Could not clear the keyring", err))?;
+            }
+            Ok(format!(
+                "Removed the stored credentials of {}",
+                profile_name
+            ))
+        }
+    }
+}
+
+// only says where each one is, never any part of it
+async fn show_credentials(profile_name: &str) -> Result<String, CommandError> {
+    let profile = AppConfig::load()
+        .map_err(|err| CommandError::failed("Could not load config", err))?
+        .profile(profile_name)
+        .unwrap_or_default();
+
+    let client_secret = match get_secret(profile_name, CLIENT_SECRET).await {
+        _ if profile.client_secret.is_some() => "stored in config.toml",
+        Ok(Some(_)) => "stored in the keyring",
+        _ if env::var("RSPOTIFY_CLIENT_SECRET").is_ok_and(|secret| !secret.is_empty()) => {
+            "from RSPOTIFY_CLIENT_SECRET"
+        }
+        Ok(None) => "not set",
+        Err(_) => "not set, the keyring is not available",
+    };
+
+    let cached_token = Token::from_cache(token_cache_path(profile_name)).ok();
+    let refresh_token = match get_secret(profile_name, REFRESH_TOKEN).await {
+        Ok(Some(_)) => "stored in the keyring",
+        _ if cached_token.is_some_and(|token| token.refresh_token.is_some()) => {
+            "stored in the token cache file"
+        }
+        _ => "not logged in",
+    };
+
+    Ok(format!(
+        "Profile: {}\nClient secret: {}\nRefresh token: {}",
+        profile_name, client_secret, refresh_token
+    ))
+}
@@ -0,0 +1,249 @@
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path, sync::Arc, time::Duration};
+use terminal_spotify::{disable_prompts, eprint_err};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    signal::unix::{signal, SignalKind},
+    sync::{broadcast, mpsc, Mutex},
+    time::timeout,
+};
+
+use crate::{
+    auth::Authorize,
+    cli::{LoginFlags, REPL_ONLY},
+    command::{parse, CommandError, Reply},
+    config::{socket_path, AppConfig, Profile},
+    events::{self, Event},
+    handlers::{execute, Session},
+    http, login, mpris,
+    output::{print_response, Output, Response},
+    Client,
+};
+
+///// PROTOCOL /////
+// one json object per line each way, ex:
+// -> {"command":"play \"album:Discovery\""}
+// <- {"ok":true,"message":"Started playing: Discovery","code":0}
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub command: String,
+}
+
+// how long ctl waits for the daemon to answer
+const CTL_TIMEOUT: Duration = Duration::from_secs(30);
+
+///// DAEMON /////
+// logs in once and keeps the session, devices and player state around so
+// commands from keybindings don't have to
+pub async fn serve(
+    profile_name: &str,
+    flags: LoginFlags,
+    http: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // there is no one to answer prompts in the background
+    disable_prompts();
+
+    let mut config = AppConfig::load()?;
+    config.http.enabled |= http;
+
+    match login(profile_name, flags).await? {
+        (_, Client::ReadOnly(_)) => {
+            Err("The daemon needs a user login, start it without --read-only".into())
+        }
+        (profile, Client::Pkce(spotify)) => listen(spotify, profile_name, &profile, config).await,
+        (profile, Client::AuthCode(spotify)) => {
+            listen(spotify, profile_name, &profile, config).await
+        }
+    }
+}
+
+// the session shared by everything the daemon serves
+pub type SharedSession<S> = Arc<Mutex<(S, Session)>>;
+
+async fn listen<S: Authorize + Send + 'static>(
+    spotify: S,
+    profile_name: &str,
+    profile: &Profile,
+    config: AppConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = socket_path(profile_name);
+    let listener = bind(&path).await?;
+
+    let mut session = Session::start(&spotify, profile_name).await?;
+    match session.activate_default_device(&spotify, profile).await {
+        Ok(Some(message)) => println!("{}", message),
+        Ok(None) => (),
+        Err(err) => eprint_err(&err.to_string()),
+    }
+
+    println!("Listening on {}", path.display());
+
+    // the poller gets its own clone so it doesn't wait for commands
+    let poller = spotify.clone();
+    let state: SharedSession<S> = Arc::new(Mutex::new((spotify, session)));
+
+    // player state changes, the poller only asks spotify while someone listens
+    let (events, _) = broadcast::channel::<Event>(64);
+    tokio::spawn(events::poll(poller, config.events.clone(), events.clone()));
+
+    events::spawn_listeners(&events, &config, false);
+
+    if config.mpris.enabled {
+        tokio::spawn(mpris::serve(state.clone(), events.clone()));
+    }
+
+    if config.http.enabled {
+        let listener = http::bind(&config.http).await?;
+        tokio::spawn(http::serve(listener, config.http, state.clone(), events));
+    }
+    // logging out stops the daemon
+    let (stop, mut stopped) = mpsc::channel::<()>(1);
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    loop {
+        tokio::select! {
+            res = listener.accept() => match res {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_client(stream, state.clone(), stop.clone()));
+                }
+                Err(err) => eprint_err(&format!("Could not accept a connection: {}", err)),
+            },
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+            _ = stopped.recv() => break,
+        }
+    }
+
+    let _ = fs::remove_file(&path);
+    println!("Stopped the daemon");
+
+    Ok(())
+}
+
+// refuses to start a second daemon, a socket left behind by one that crashed
+// is removed
+async fn bind(path: &Path) -> Result<UnixListener, Box<dyn std::error::Error>> {
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(format!("A daemon is already running on {}", path.display()).into());
+        }
+        fs::remove_file(path)?;
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+
+    // only the user may send commands
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+
+    Ok(listener)
+}
+
+async fn handle_client<S: Authorize + Send + 'static>(
+    stream: UnixStream,
+    state: SharedSession<S>,
+    stop: mpsc::Sender<()>,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (response, exit) = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => run_request(&state, &request.command).await,
+            Err(err) => (
+                Response::err(CommandError::Usage(format!("Invalid request: {}", err))),
+                false,
+            ),
+        };
+
+        let mut json = serde_json::to_string(&response).unwrap();
+        json.push('\n');
+        if writer.write_all(json.as_bytes()).await.is_err() {
+            return;
+        }
+
+        if exit {
+            let _ = stop.send(()).await;
+            return;
+        }
+    }
+}
+
+// runs one command, true if the daemon should stop after answering
+pub async fn run_request<S: Authorize>(
+    state: &Mutex<(S, Session)>,
+    input: &str,
+) -> (Response, bool) {
+    let command = match parse(input) {
+        Ok(command) => command,
+        Err(err) => return (Response::err(err.into()), false),
+    };
+
+    if command.name() != "help" && REPL_ONLY.contains(&command.name()) {
+        return (
+            Response::err(CommandError::Usage(format!(
+                "{} only works in the repl",
+                command.name()
+            ))),
+            false,
+        );
+    }
+
+    // one command at a time, they share the session
+    let mut state = state.lock().await;
+    let (spotify, session) = &mut *state;
+
+    let res = execute(spotify, session, command).await;
+    let exit = matches!(res, Ok(Reply::Exit(_)));
+    (Response::from_result(res), exit)
+}
+
+///// CTL /////
+// sends a command to the daemon and prints the answer, returns the exit code
+pub async fn ctl(profile_name: &str, words: Vec<String>, output: Output) -> i32 {
+    match send(profile_name, &shell_words::join(words)).await {
+        Ok(response) => print_response(&response, output),
+        Err(err) => {
+            eprint_err(&err.to_string());
+            1
+        }
+    }
+}
+
+async fn send(profile_name: &str, command: &str) -> Result<Response, Box<dyn std::error::Error>> {
+    let path = socket_path(profile_name);
+    let stream = match UnixStream::connect(&path).await {
+        Ok(stream) => stream,
+        Err(_) => {
+            return Err("The daemon is not running, start it with `terminal-spotify daemon`".into())
+        }
+    };
+
+    let (reader, mut writer) = stream.into_split();
+
+    let mut request = serde_json::to_string(&Request {
+        command: command.to_string(),
+    })?;
+    request.push('\n');
+    writer.write_all(request.as_bytes()).await?;
+
+    let mut lines = BufReader::new(reader).lines();
+    match timeout(CTL_TIMEOUT, lines.next_line()).await {
+        Ok(Ok(Some(line))) => Ok(serde_json::from_str(&line)?),
+        Ok(Ok(None)) => Err("The daemon closed the connection".into()),
+        Ok(Err(err)) => Err(err.into()),
+        Err(_) => Err("Timed out waiting for the daemon".into()),
+    }
+}
@@ -0,0 +1,302 @@
+use rustyline::{
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::{FileHistory, History},
+    validate::Validator,
+    CompletionType, Config, Context, Editor, Helper,
+};
+use std::{fs, path::PathBuf};
+use terminal_spotify::eprint_err;
+
+use crate::{
+    command::{find_command, CommandDef, COMMANDS},
+    config::{profile_dir, AppConfig, EditMode},
+};
+
+///// LINE EDITOR /////
+// the repl prompt and the search prompts, with emacs or vi keys and a history
+// per profile that is kept between sessions. commands and search queries have
+// their own history, up at "Search for a song" only goes through searches
+
+#[derive(Debug, Clone, Copy)]
+pub enum HistoryKind {
+    Commands,
+    Searches,
+}
+
+impl HistoryKind {
+    fn file_name(self) -> &'static str {
+        match self {
+            HistoryKind::Commands => "history",
+            HistoryKind::Searches => "search_history",
+        }
+    }
+}
+
+pub struct LineEditor {
+    editor: Editor<Completions, FileHistory>,
+    path: PathBuf,
+    profile_name: String,
+    kind: HistoryKind,
+}
+
+impl LineEditor {
+    pub fn open(profile_name: &str, kind: HistoryKind) -> Result<LineEditor, ReadlineError> {
+        let config = AppConfig::load().unwrap_or_default().editor(profile_name);
+
+        let mut editor = Editor::with_config(
+            Config::builder()
+                .edit_mode(match config.mode {
+                    EditMode::Emacs => rustyline::EditMode::Emacs,
+                    EditMode::Vi => rustyline::EditMode::Vi,
+                })
+                .max_history_size(config.history_size.max(1))?
+                .history_ignore_dups(true)?
+                // a line starting with a space isn't remembered, like in bash
+                .history_ignore_space(true)
+                // all the candidates at once like a shell, not one per tab
+                .completion_type(CompletionType::List)
+                .build(),
+        )?;
+
+        let path = history_path(profile_name, kind);
+        // there is no history yet the first time
+        let _ = editor.load_history(&path);
+
+        let mut line_editor = LineEditor {
+            editor,
+            path,
+            profile_name: profile_name.to_string(),
+            kind,
+        };
+        line_editor.complete_with(Completions::default());
+        Ok(line_editor)
+    }
+
+    // what tab completes to from now on, the searches come from the search
+    // history so the ones made since the last prompt are in there too
+    pub fn complete_with(&mut self, mut completions: Completions) {
+        if let HistoryKind::Commands = self.kind {
+            completions.searches = recent_searches(&self.profile_name);
+            self.editor.set_helper(Some(completions));
+        }
+    }
+
+    // None when the input ends (ctrl-d), ctrl-c clears the line
+    pub fn read(&mut self, prompt: &str) -> Option<String> {
+        match self.editor.readline(prompt) {
+            Ok(line) => {
+                self.remember(&line);
+                Some(line)
+            }
+            Err(ReadlineError::Interrupted) => Some(String::new()),
+            Err(_) => None,
+        }
+    }
+
+    // the same line again moves it to the end instead of being in there twice
+    pub fn remember(&mut self, line: &str) {
+        if line.trim().is_empty() || line.starts_with(' ') {
+            return;
+        }
+
+        let history = self.editor.history_mut();
+        let others: Vec<String> = history
+            .iter()
+            .filter(|entry| entry.as_str() != line)
+            .cloned()
+            .collect();
+        if others.len() != history.len() {
+            let _ = history.clear();
+            for entry in others {
+                let _ = history.add_owned(entry);
+            }
+        }
+        let _ = history.add(line);
+
+        if let Some(dir) = self.path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        if let Err(err) = self.editor.save_history(&self.path) {
+            eprint_err(&format!("Could not save the history: {}", err));
+        }
+    }
+}
+
+fn history_path(profile_name: &str, kind: HistoryKind) -> PathBuf {
+    profile_dir(profile_name).join(kind.file_name())
+}
+
+// queries given with the command go in the search history too, so they come
+// up at the prompt
+pub fn remember_search(profile_name: &str, query: &str) {
+    if let Ok(mut editor) = LineEditor::open(profile_name, HistoryKind::Searches) {
+        editor.remember(query);
+    }
+}
+
+// newest first
+fn recent_searches(profile_name: &str) -> Vec<String> {
+    let mut history = FileHistory::new();
+    if history
+        .load(&history_path(profile_name, HistoryKind::Searches))
+        .is_err()
+    {
+        return vec![];
+    }
+    history.iter().rev().cloned().collect()
+}
+
+///// COMPLETION /////
+// tab at the repl prompt, first the command and after that what the command
+// takes: flags, a device for activate, a playlist for playlist and earlier
+// searches and artists for the commands that search
+
+#[derive(Debug, Default)]
+pub struct Completions {
+    pub devices: Vec<String>,
+    pub playlists: Vec<String>,
+    pub artists: Vec<String>,
+    // complete_with fills these in from the search history
+    pub searches: Vec<String>,
+}
+
+impl Completions {
+    fn candidates(&self, line: &str) -> (usize, Vec<Pair>) {
+        let command = line.trim_start();
+        let command_start = line.len() - command.len();
+
+        let (name, rest) = match command.split_once(' ') {
+            Some(split) => split,
+            // still typing the command
+            None => return (command_start, command_names(command)),
+        };
+        let def = match find_command(name) {
+            Some(def) => def,
+            None => return (line.len(), vec![]),
+        };
+
+        // free text from after the command, ex: a device name with spaces in it
+        let arg = rest.trim_start();
+        let arg_start = line.len() - arg.len();
+        // just the last word, for flags
+        let word_start = line.rfind(' ').map_or(0, |i| i + 1);
+
+        match def.name {
+            "activate" => (arg_start, matching(&self.devices, arg)),
+            "playlist" => (arg_start, matching(&self.playlists, arg)),
+            "song" | "album" | "queue" => (arg_start, matching(&self.searched(), arg)),
+            // play playlist:name, play album:query, play query
+            "play" => match arg.split_once(':') {
+                Some(("playlist", name)) => {
+                    let name = name.trim_start();
+                    (line.len() - name.len(), matching(&self.playlists, name))
+                }
+                Some(("album" | "song" | "track", query)) => {
+                    let query = query.trim_start();
+                    (line.len() - query.len(), matching(&self.searched(), query))
+                }
+                _ => {
+                    let mut choices: Vec<String> = ["album:", "playlist:", "song:"]
+                        .iter()
+                        .map(|prefix| prefix.to_string())
+                        .collect();
+                    choices.extend(self.searched());
+                    (arg_start, matching(&choices, arg))
+                }
+            },
+            _ => (word_start, flags(def, &line[word_start..])),
+        }
+    }
+
+    // earlier queries and the artists of what they played
+    fn searched(&self) -> Vec<String> {
+        self.searches
+            .iter()
+            .chain(self.artists.iter())
+            .cloned()
+            .collect()
+    }
+}
+
+impl Completer for Completions {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        Ok(self.candidates(&line[..pos]))
+    }
+}
+
+impl Hinter for Completions {
+    type Hint = String;
+}
+
+impl Highlighter for Completions {}
+
+impl Validator for Completions {}
+
+impl Helper for Completions {}
+
+fn command_names(typed: &str) -> Vec<Pair> {
+    let mut names: Vec<&str> = COMMANDS
+        .iter()
+        .flat_map(|def| std::iter::once(def.name).chain(def.aliases.iter().copied()))
+        .filter(|name| name.starts_with(typed))
+        .collect();
+    names.sort();
+    names.dedup();
+
+    names
+        .into_iter()
+        .map(|name| Pair {
+            display: name.to_string(),
+            replacement: format!("{} ", name),
+        })
+        .collect()
+}
+
+// the flags and fixed words from the usage, [--json] [--format <template>] or
+// set|show|clear
+fn flags(def: &CommandDef, typed: &str) -> Vec<Pair> {
+    let fixed_words = !def.args.contains(['[', '<']);
+    def.args
+        .split(|c: char| !(c.is_alphanumeric() || c == '-'))
+        .filter(|word| !word.is_empty() && (word.starts_with("--") || fixed_words))
+        .filter(|word| word.starts_with(typed))
+        .map(|word| Pair {
+            display: word.to_string(),
+            replacement: format!("{} ", word),
+        })
+        .collect()
+}
+
+// names that start with what is typed, whatever the case. the parser splits
+// like a shell so a name with a quote in it is quoted
+fn matching(choices: &[String], typed: &str) -> Vec<Pair> {
+    let typed = typed.trim_start_matches(['\'', '"']).to_lowercase();
+    let mut pairs: Vec<Pair> = vec![];
+    for choice in choices {
+        if !choice.to_lowercase().starts_with(&typed)
+            || pairs.iter().any(|pair| &pair.display == choice)
+        {
+            continue;
+        }
+        let replacement = if choice.contains(['\'', '"', '\\']) {
+            shell_words::quote(choice).to_string()
+        } else {
+            choice.clone()
+        };
+        pairs.push(Pair {
+            display: choice.clone(),
+            replacement,
+        });
+    }
+    pairs
+}
@@ -0,0 +1,526 @@
+use chrono::TimeDelta;
+use rspotify::{
+    model::{Image, PlayableItem, RepeatState},
+    prelude::*,
+};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use terminal_spotify::serialize_millis;
+use tokio::{sync::broadcast, task::JoinHandle, time::sleep};
+
+use crate::{
+    auth::{reauth, Authorize},
+    command::CommandError,
+    config::{AppConfig, EventsConfig},
+    notify, scrobble,
+};
+
+///// PLAYER STATE /////
+// what the poller compares between two polls
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct PlayerState {
+    pub track_id: Option<String>,
+    pub title: String,
+    pub artists: Vec<String>,
+    pub album: String,
+    #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+    pub duration: TimeDelta,
+    pub is_playing: bool,
+    #[serde(rename = "progress_ms", serialize_with = "serialize_millis")]
+    pub progress: TimeDelta,
+    pub device_id: Option<String>,
+    pub device_name: Option<String>,
+    pub volume: Option<u32>,
+    pub shuffle: bool,
+    // off, track or context
+    pub repeat: String,
+    pub cover_url: Option<String>,
+}
+
+pub async fn get_player_state(spotify: &impl Authorize) -> Result<PlayerState, CommandError> {
+    let playback = reauth(spotify, || spotify.current_playback(None, None::<Vec<_>>))
+        .await
+        .map_err(|err| CommandError::failed("Could not get the player state", err))?;
+
+    // nothing has been played for a while
+    let playback = match playback {
+        Some(playback) => playback,
+        None => {
+            return Ok(PlayerState {
+                repeat: String::from("off"),
+                ..Default::default()
+            })
+        }
+    };
+
+    let mut state = PlayerState {
+        is_playing: playback.is_playing,
+        progress: playback.progress.unwrap_or_default(),
+        device_id: playback.device.id,
+        device_name: Some(playback.device.name),
+        volume: playback.device.volume_percent,
+        shuffle: playback.shuffle_state,
+        repeat: String::from(match playback.repeat_state {
+            RepeatState::Off => "off",
+            RepeatState::Track => "track",
+            RepeatState::Context => "context",
+        }),
+        ..Default::default()
+    };
+
+    match playback.item {
+        Some(PlayableItem::Track(track)) => {
+            state.track_id = track.id.map(|id| id.uri());
+            state.title = track.name;
+            state.artists = track
+                .artists
+                .into_iter()
+                .map(|artist| artist.name)
+                .collect();
+            state.cover_url = cover_url(&track.album.images);
+            state.album = track.album.name;
+            state.duration = track.duration;
+        }
+        Some(PlayableItem::Episode(episode)) => {
+            state.track_id = Some(episode.id.uri());
+            state.cover_url = cover_url(&episode.images);
+            state.title = episode.name;
+            state.album = episode.show.name;
+            state.duration = episode.duration;
+        }
+        None => (),
+    }
+
+    Ok(state)
+}
+
+// the image closest to 300px, big enough for a notification
+fn cover_url(images: &[Image]) -> Option<String> {
+    images
+        .iter()
+        .min_by_key(|image| (image.width.unwrap_or(0) as i64 - 300).abs())
+        .map(|image| image.url.clone())
+}
+
+impl PlayerState {
+    // catches up with an event from the poller
+    pub fn apply(&mut self, event: &Event) {
+        match event {
+            Event::TrackChanged { track } => *self = track.clone(),
+            Event::Paused { progress_ms } | Event::Resumed { progress_ms } => {
+                self.is_playing = matches!(event, Event::Resumed { .. });
+                self.progress = TimeDelta::try_milliseconds(*progress_ms).unwrap_or_default();
+            }
+            Event::Seeked { progress_ms } => {
+                self.progress = TimeDelta::try_milliseconds(*progress_ms).unwrap_or_default()
+            }
+            Event::DeviceChanged { id, name } => {
+                self.device_id = id.clone();
+                self.device_name = name.clone();
+            }
+            Event::VolumeChanged { percent } => self.volume = Some(*percent),
+        }
+    }
+}
+
+///// EVENTS /////
+// sent as json, ex: {"event":"volume_changed","percent":40}
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    TrackChanged {
+        track: PlayerState,
+    },
+    Paused {
+        progress_ms: i64,
+    },
+    Resumed {
+        progress_ms: i64,
+    },
+    Seeked {
+        progress_ms: i64,
+    },
+    DeviceChanged {
+        id: Option<String>,
+        name: Option<String>,
+    },
+    VolumeChanged {
+        percent: u32,
+    },
+}
+
+// how far the progress may drift from where it should be before it counts as
+// a seek, polls don't land exactly on time
+const SEEK_TOLERANCE_MS: i64 = 3000;
+
+// what changed between two polls, `elapsed` is the time between them
+pub fn diff(old: &PlayerState, new: &PlayerState, elapsed: Duration) -> Vec<Event> {
+    let mut events = Vec::new();
+    let progress_ms = new.progress.num_milliseconds();
+
+    if old.device_id != new.device_id {
+        events.push(Event::DeviceChanged {
+            id: new.device_id.clone(),
+            name: new.device_name.clone(),
+        });
+    }
+
+    if old.track_id != new.track_id || old.title != new.title {
+        events.push(Event::TrackChanged { track: new.clone() });
+    } else if old.is_playing && new.is_playing {
+        let expected = old.progress + TimeDelta::from_std(elapsed).unwrap_or_default();
+        if (new.progress - expected).num_milliseconds().abs() > SEEK_TOLERANCE_MS {
+            events.push(Event::Seeked { progress_ms });
+        }
+    } else if old.progress != new.progress && old.is_playing == new.is_playing {
+        // moved while paused
+        events.push(Event::Seeked { progress_ms });
+    }
+
+    match (old.is_playing, new.is_playing) {
+        (true, false) => events.push(Event::Paused { progress_ms }),
+        (false, true) => events.push(Event::Resumed { progress_ms }),
+        _ => (),
+    }
+
+    if let Some(percent) = new.volume {
+        if old.volume != new.volume {
+            events.push(Event::VolumeChanged { percent });
+        }
+    }
+
+    events
+}
+
+///// POLLER /////
+// asks spotify for the player state and sends what changed to everyone
+// subscribed, runs until it's aborted. the client is a clone of the session's,
+// they share the token
+pub async fn poll<S: Authorize + 'static>(
+    spotify: S,
+    config: EventsConfig,
+    events: broadcast::Sender<Event>,
+) {
+    let (poll_interval, idle_interval) = config.intervals();
+
+    let mut last: Option<(Instant, PlayerState)> = None;
+    let mut interval = poll_interval;
+
+    loop {
+        sleep(interval).await;
+
+        // no one to tell, start over when someone subscribes
+        if events.receiver_count() == 0 {
+            last = None;
+            interval = poll_interval;
+            continue;
+        }
+
+        let player_state = get_player_state(&spotify).await;
+
+        // spotify is unreachable, try again later
+        let Ok(player_state) = player_state else {
+            interval = (interval * 2).min(idle_interval);
+            continue;
+        };
+
+        let changes = match &last {
+            Some((polled_at, old)) => diff(old, &player_state, polled_at.elapsed()),
+            // tell the new subscribers what is playing
+            None if !player_state.title.is_empty() => vec![Event::TrackChanged {
+                track: player_state.clone(),
+            }],
+            None => Vec::new(),
+        };
+
+        // back off while nothing plays and nothing happens
+        interval = if player_state.is_playing || !changes.is_empty() {
+            poll_interval
+        } else {
+            (interval * 2).min(idle_interval)
+        };
+
+        for event in changes {
+            // only fails when everyone unsubscribed in the meantime
+            let _ = events.send(event);
+        }
+
+        last = Some((Instant::now(), player_state));
+    }
+}
+
+///// LISTENERS /////
+// starts what reacts to player changes and is turned on in the config,
+// `terminal` is true in the repl
+pub fn spawn_listeners(
+    events: &broadcast::Sender<Event>,
+    config: &AppConfig,
+    terminal: bool,
+) -> Vec<JoinHandle<()>> {
+    let mut tasks = Vec::new();
+
+    if config.notifications.enabled {
+        tasks.push(tokio::spawn(notify::notify_tracks(
+            events.subscribe(),
+            config.notifications.clone(),
+            terminal,
+        )));
+    }
+
+    if config.scrobble.lastfm.is_some() || config.scrobble.listenbrainz.is_some() {
+        tasks.push(tokio::spawn(scrobble::scrobble_tracks(
+            events.subscribe(),
+            config.scrobble.clone(),
+        )));
+    }
+
+    tasks
+}
+
+// the poller and the listeners for the repl, which has no poller of its own
+// like the daemon. they stop when this is dropped
+pub struct Watcher {
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+pub fn watch(spotify: &(impl Authorize + 'static), config: &AppConfig) -> Option<Watcher> {
+    let (events, _) = broadcast::channel::<Event>(64);
+
+    let mut tasks = spawn_listeners(&events, config, true);
+    if tasks.is_empty() {
+        return None;
+    }
+    tasks.push(tokio::spawn(poll(
+        spotify.clone(),
+        config.events.clone(),
+        events,
+    )));
+
+    Some(Watcher { tasks })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(seconds: i64) -> TimeDelta {
+        TimeDelta::try_seconds(seconds).unwrap()
+    }
+
+    fn playing() -> PlayerState {
+        PlayerState {
+            track_id: Some(String::from("spotify:track:1")),
+            title: String::from("One More Time"),
+            artists: vec![String::from("Daft Punk")],
+            album: String::from("Discovery"),
+            duration: seconds(320),
+            is_playing: true,
+            progress: seconds(60),
+            device_id: Some(String::from("kitchen")),
+            device_name: Some(String::from("Kitchen")),
+            volume: Some(40),
+            repeat: String::from("off"),
+            ..Default::default()
+        }
+    }
+
+    // what a poll `elapsed` later finds when nothing was touched
+    fn later(state: &PlayerState, elapsed: Duration) -> PlayerState {
+        let mut state = state.clone();
+        state.progress += TimeDelta::from_std(elapsed).unwrap();
+        state
+    }
+
+    #[test]
+    fn nothing_changed() {
+        let old = playing();
+        assert!(diff(&old, &old, Duration::ZERO).is_empty());
+
+        let elapsed = Duration::from_secs(5);
+        assert!(diff(&old, &later(&old, elapsed), elapsed).is_empty());
+    }
+
+    #[test]
+    fn polls_that_come_late_are_not_seeks() {
+        let old = playing();
+        let mut new = later(&old, Duration::from_secs(5));
+        new.progress += TimeDelta::try_milliseconds(SEEK_TOLERANCE_MS).unwrap();
+        assert!(diff(&old, &new, Duration::from_secs(5)).is_empty());
+    }
+
+    #[test]
+    fn track_changed() {
+        let old = playing();
+        let mut new = playing();
+        new.track_id = Some(String::from("spotify:track:2"));
+        new.title = String::from("Aerodynamic");
+        new.progress = seconds(0);
+
+        let events = diff(&old, &new, Duration::from_secs(1));
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0], Event::TrackChanged { track } if *track == new));
+    }
+
+    #[test]
+    fn track_changed_without_an_id() {
+        // local files and some episodes have no id, the title tells them apart
+        let mut old = playing();
+        old.track_id = None;
+        let mut new = old.clone();
+        new.title = String::from("Digital Love");
+
+        let events = diff(&old, &new, Duration::ZERO);
+        assert!(matches!(events[..], [Event::TrackChanged { .. }]));
+    }
+
+    #[test]
+    fn seeked_while_playing() {
+        let old = playing();
+        let mut new = later(&old, Duration::from_secs(1));
+        new.progress = seconds(200);
+
+        let events = diff(&old, &new, Duration::from_secs(1));
+        assert!(matches!(
+            events[..],
+            [Event::Seeked {
+                progress_ms: 200_000
+            }]
+        ));
+
+        // back counts too
+        new.progress = seconds(10);
+        let events = diff(&old, &new, Duration::from_secs(1));
+        assert!(matches!(
+            events[..],
+            [Event::Seeked {
+                progress_ms: 10_000
+            }]
+        ));
+    }
+
+    #[test]
+    fn seeked_while_paused() {
+        let mut old = playing();
+        old.is_playing = false;
+        let mut new = old.clone();
+        new.progress = seconds(90);
+
+        let events = diff(&old, &new, Duration::from_secs(30));
+        assert!(matches!(
+            events[..],
+            [Event::Seeked {
+                progress_ms: 90_000
+            }]
+        ));
+    }
+
+    #[test]
+    fn paused_and_resumed() {
+        let old = playing();
+        let mut paused = old.clone();
+        paused.is_playing = false;
+        paused.progress = seconds(61);
+
+        let events = diff(&old, &paused, Duration::from_secs(1));
+        assert!(matches!(
+            events[..],
+            [Event::Paused {
+                progress_ms: 61_000
+            }]
+        ));
+
+        let events = diff(&paused, &old, Duration::from_secs(1));
+        assert!(matches!(
+            events[..],
+            [Event::Resumed {
+                progress_ms: 60_000
+            }]
+        ));
+    }
+
+    #[test]
+    fn device_changed() {
+        let old = playing();
+        let mut new = old.clone();
+        new.device_id = Some(String::from("laptop"));
+        new.device_name = Some(String::from("Laptop"));
+
+        let events = diff(&old, &new, Duration::ZERO);
+        assert!(matches!(
+            &events[..],
+            [Event::DeviceChanged { id: Some(id), name: Some(name) }]
+                if id == "laptop" && name == "Laptop"
+        ));
+
+        // and when there is none anymore
+        new.device_id = None;
+        new.device_name = None;
+        let events = diff(&old, &new, Duration::ZERO);
+        assert!(matches!(
+            events[..],
+            [Event::DeviceChanged {
+                id: None,
+                name: None
+            }]
+        ));
+    }
+
+    #[test]
+    fn volume_changed() {
+        let old = playing();
+        let mut new = old.clone();
+        new.volume = Some(70);
+
+        let events = diff(&old, &new, Duration::ZERO);
+        assert!(matches!(events[..], [Event::VolumeChanged { percent: 70 }]));
+
+        // a device that doesn't tell its volume is not a change
+        new.volume = None;
+        assert!(diff(&old, &new, Duration::ZERO).is_empty());
+    }
+
+    #[test]
+    fn everything_at_once_comes_in_order() {
+        let mut old = playing();
+        old.is_playing = false;
+        let mut new = playing();
+        new.track_id = Some(String::from("spotify:track:2"));
+        new.device_id = Some(String::from("laptop"));
+        new.volume = Some(10);
+
+        let events = diff(&old, &new, Duration::ZERO);
+        assert!(matches!(
+            events[..],
+            [
+                Event::DeviceChanged { .. },
+                Event::TrackChanged { .. },
+                Event::Resumed { .. },
+                Event::VolumeChanged { percent: 10 }
+            ]
+        ));
+    }
+
+    #[test]
+    fn apply_catches_up() {
+        let old = playing();
+        let mut new = later(&old, Duration::from_secs(2));
+        new.is_playing = false;
+        new.volume = Some(55);
+        new.device_id = Some(String::from("laptop"));
+        new.device_name = Some(String::from("Laptop"));
+
+        let mut state = old.clone();
+        for event in diff(&old, &new, Duration::from_secs(2)) {
+            state.apply(&event);
+        }
+        assert_eq!(state, new);
+    }
+}
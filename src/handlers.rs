@@ -0,0 +1,549 @@
+use chrono::Duration;
+use colored::Colorize;
+use std::{collections::HashMap, io::Write, time::Instant};
+use terminal_spotify::{can_prompt, eprint_err, format_duration, progress_bar, you_can_not_leave};
+use tokio::time::sleep;
+
+use crate::{
+    activate_device,
+    auth::{ensure_scopes, granted_scopes, logout, logout_all, missing_scopes, reauth, Authorize},
+    command::{help_text, Command, CommandError, Reply, MAX_SEEK_SECONDS},
+    config::{EventsConfig, Profile},
+    context_name,
+    credentials::credentials_command,
+    current_user,
+    editor::Completions,
+    events::get_player_state,
+    find_active_device, get_available_devices, get_currently_playing, get_queue, list_devices,
+    list_queue, play_uri, query_or_ask, queue_song, search_album, search_song, seek_seconds,
+    select_playlist, select_profile,
+    template::render,
+    to_devices,
+    watch::watch,
+    CurrentlyPlaying, Device,
+};
+
+///// SESSION /////
+// what the handlers keep between commands, the repl and the daemon keep one
+// for as long as they run and the cli makes a new one for every command
+pub struct Session {
+    pub profile_name: String,
+    pub devices: Vec<(String, String, bool)>,
+    pub active_device: Device,
+    // the last fetched player state and when it was fetched
+    playing: Option<(Instant, CurrentlyPlaying)>,
+    // playlist/artist uri -> name, for what status says it plays from
+    context_names: HashMap<String, String>,
+    // the user's playlist names and the artists of what was played or
+    // queued, for tab completion
+    pub playlists: Vec<String>,
+    artists: Vec<String>,
+}
+
+// how long the player state is trusted before spotify is asked again
+const PLAYING_CACHE_TIME: std::time::Duration = std::time::Duration::from_secs(3);
+// how many artists tab completes to
+const REMEMBERED_ARTISTS: usize = 50;
+
+impl Session {
+    pub async fn start(
+        spotify: &impl Authorize,
+        profile_name: &str,
+    ) -> Result<Session, CommandError> {
+        let devices = get_available_devices(spotify).await?;
+
+        Ok(Session {
+            profile_name: profile_name.to_string(),
+            active_device: find_active_device(&devices),
+            devices,
+            playing: None,
+            context_names: HashMap::new(),
+            playlists: vec![],
+            artists: vec![],
+        })
+    }
+
+    // the cached player state, the progress is moved along by the time that
+    // passed since it was fetched
+    pub async fn currently_playing(
+        &mut self,
+        spotify: &impl Authorize,
+    ) -> Result<CurrentlyPlaying, CommandError> {
+        if let Some((fetched_at, playing)) = &self.playing {
+            let elapsed = fetched_at.elapsed();
+            if elapsed < PLAYING_CACHE_TIME {
+                return Ok(playing.moved_along(elapsed));
+            }
+        }
+
+        let playing = get_currently_playing(spotify).await?;
+        self.playing = Some((Instant::now(), playing.clone()));
+        Ok(playing)
+    }
+
+    // the next currently_playing asks spotify again
+    pub fn forget_playing(&mut self) {
+        self.playing = None;
+    }
+
+    // the player state with the name of what it plays from filled in
+    pub async fn now_playing(
+        &mut self,
+        spotify: &impl Authorize,
+    ) -> Result<CurrentlyPlaying, CommandError> {
+        let mut playing = self.currently_playing(spotify).await?;
+
+        if let Some(context) = &mut playing.context {
+            if context.name.is_none() {
+                if let Some(name) = self.context_names.get(&context.uri) {
+                    context.name = Some(name.clone());
+                } else if let Some(name) = context_name(spotify, context).await {
+                    self.context_names.insert(context.uri.clone(), name.clone());
+                    context.name = Some(name);
+                }
+            }
+        }
+
+        Ok(playing)
+    }
+
+    // what tab completes to at the repl prompt
+    pub fn completions(&self) -> Completions {
+        Completions {
+            devices: self
+                .devices
+                .iter()
+                .map(|(_, name, _)| name.clone())
+                .collect(),
+            playlists: self.playlists.clone(),
+            artists: self.artists.clone(),
+            ..Default::default()
+        }
+    }
+
+    // the artists of the song or album a search played or queued
+    pub fn remember_artists(&mut self, reply: &Reply) {
+        let data = match reply {
+            Reply::Data { data, .. } => data,
+            _ => return,
+        };
+        for key in ["played", "queued"] {
+            let artists = match data[key]["artists"].as_array() {
+                Some(artists) => artists,
+                None => continue,
+            };
+            for artist in artists.iter().filter_map(|artist| artist.as_str()) {
+                self.artists.retain(|known| known != artist);
+                self.artists.insert(0, artist.to_string());
+            }
+        }
+        self.artists.truncate(REMEMBERED_ARTISTS);
+    }
+
+    // activates the profile's default device when nothing is active
+    pub async fn activate_default_device(
+        &mut self,
+        spotify: &impl Authorize,
+        profile: &Profile,
+    ) -> Result<Option<String>, CommandError> {
+        let default_device = match &profile.default_device {
+            Some(device) if self.active_device.id.is_empty() => device,
+            _ => return Ok(None),
+        };
+
+        activate_device(
+            spotify,
+            &self.devices,
+            Some(default_device),
+            &mut self.active_device,
+        )
+        .await
+        .map(Some)
+    }
+
+    fn require_device(&self, text: &'static str) -> Result<String, CommandError> {
+        if self.active_device.id.is_empty() {
+            return Err(CommandError::NoDevice(text));
+        }
+        Ok(self.active_device.id.clone())
+    }
+}
+
+// a position spotify can seek to, whatever the command or the config said
+fn seek_position(seconds: i64) -> Result<Duration, CommandError> {
+    Duration::try_seconds(seconds)
+        .filter(|_| (0..=MAX_SEEK_SECONDS).contains(&seconds))
+        .ok_or_else(|| CommandError::Usage(format!("Can't seek to {} seconds", seconds)))
+}
+
+///// HANDLERS /////
+// runs a command for the repl and the cli alike
+pub async fn execute(
+    spotify: &mut impl Authorize,
+    session: &mut Session,
+    command: Command,
+) -> Result<Reply, CommandError> {
+    // re-authorize first if the command needs scopes the token doesn't have
+    ensure_scopes(spotify, command.scopes()).await?;
+
+    let res = run_command(spotify, session, command.clone()).await;
+
+    // a refreshed token can come back with fewer scopes and spotify refuses
+    // the request then, ask for the ones the command needs and try once more.
+    // a refusal with them granted is something else, ex: no premium
+    if res.is_err() && !missing_scopes(&granted_scopes(spotify).await, command.scopes()).is_empty()
+    {
+        ensure_scopes(spotify, command.scopes()).await?;
+        return run_command(spotify, session, command).await;
+    }
+    res
+}
+
+async fn run_command(
+    spotify: &impl Authorize,
+    session: &mut Session,
+    command: Command,
+) -> Result<Reply, CommandError> {
+    // anything but status and watch can change what is playing
+    if !matches!(command, Command::Status { .. } | Command::Watch) {
+        session.forget_playing();
+    }
+
+    match command {
+        Command::Help => Ok(Reply::Message(help_text(false))),
+        Command::Activate(name) => activate_device(
+            spotify,
+            &session.devices,
+            name.as_deref(),
+            &mut session.active_device,
+        )
+        .await
+        .map(|text| Reply::data(text, &session.active_device)),
+        Command::Devices => {
+            session.devices = get_available_devices(spotify).await?;
+            let text = list_devices(&session.devices, &mut session.active_device)?;
+            Ok(Reply::data(text, to_devices(&session.devices)))
+        }
+        Command::Song(query) => {
+            let q = query_or_ask(query, "Search for a song", &session.profile_name)?;
+            search_song(spotify, &q, &mut session.active_device).await
+        }
+        Command::Album(query) => {
+            let q = query_or_ask(query, "Search for an album", &session.profile_name)?;
+            search_album(spotify, &q, &mut session.active_device).await
+        }
+        Command::Playlist(name) => {
+            select_playlist(
+                spotify,
+                name.as_deref(),
+                &mut session.active_device,
+                &mut session.playlists,
+            )
+            .await
+        }
+        Command::Toggle => {
+            let device = session.require_device("Can't resume/pause playback")?;
+            if session.currently_playing(spotify).await?.is_playing {
+                return pause(spotify, &device).await;
+            }
+            resume(spotify, &device).await
+        }
+        Command::Play => {
+            let device = session.require_device("Can't resume playback")?;
+            if session.currently_playing(spotify).await?.is_playing {
+                return Ok(Reply::Message("Already playing".to_string()));
+            }
+            resume(spotify, &device).await
+        }
+        Command::PlayUri { uri, offset } => {
+            session.require_device("Can't start playback")?;
+            play_uri(spotify, &uri, offset.as_deref(), &session.active_device).await
+        }
+        Command::Pause => {
+            let device = session.require_device("Can't pause playback")?;
+            if !session.currently_playing(spotify).await?.is_playing {
+                return Ok(Reply::Message("Already paused".to_string()));
+            }
+            pause(spotify, &device).await
+        }
+        Command::Restart => {
+            let device = session.require_device("Can't restart the track")?;
+            // use seek to position to set position to 0 ms
+            reauth(spotify, || {
+                spotify.seek_track(Duration::zero(), Some(&device))
+            })
+            .await
+            .map_err(|err| CommandError::failed("Could not restart track", err))?;
+            Ok(Reply::Message("Restarted track".to_string()))
+        }
+        Command::Next => {
+            let device = session.require_device("Can't skip to the next track")?;
+            reauth(spotify, || spotify.next_track(Some(&device)))
+                .await
+                .map_err(|err| CommandError::failed("Could not skip to next track", err))?;
+            Ok(Reply::Message("Skipped to next track".to_string()))
+        }
+        Command::Prev => {
+            let device = session.require_device("Can't skip to the previous track")?;
+            reauth(spotify, || spotify.previous_track(Some(&device)))
+                .await
+                .map_err(|err| CommandError::failed("Could not skip to previous track", err))?;
+            Ok(Reply::Message("Skipped to previous track".to_string()))
+        }
+        Command::Forward(seconds) => {
+            let seconds = seek_seconds(seconds, "forward")?;
+            let device = session.require_device("Can't skip forward")?;
+            let playing = session.currently_playing(spotify).await?;
+            let progress = playing.progress.unwrap_or(Duration::zero()).num_seconds();
+            let mut seconds_forward = progress.saturating_add(seconds);
+            // past the end would skip to the next track
+            if playing.duration > Duration::zero() {
+                seconds_forward = seconds_forward.min(playing.duration.num_seconds());
+            }
+            let position = seek_position(seconds_forward)?;
+
+            reauth(spotify, || spotify.seek_track(position, Some(&device)))
+                .await
+                .map_err(|err| CommandError::failed("Could not skip forward", err))?;
+            Ok(Reply::Message(format!(
+                "Skipped forward {} seconds",
+                seconds
+            )))
+        }
+        Command::Back(seconds) => {
+            let seconds = seek_seconds(seconds, "back")?;
+            let device = session.require_device("Can't skip back")?;
+            let progress = session.currently_playing(spotify).await?.progress;
+            let seconds_back = progress
+                .unwrap_or(Duration::zero())
+                .num_seconds()
+                .saturating_sub(seconds)
+                .max(0);
+            let position = seek_position(seconds_back)?;
+
+            reauth(spotify, || spotify.seek_track(position, Some(&device)))
+                .await
+                .map_err(|err| CommandError::failed("Could not skip back", err))?;
+            Ok(Reply::Message(format!("Skipped back {} seconds", seconds)))
+        }
+        Command::Seek(position) => {
+            let position = seek_position(position)?;
+            let device = session.require_device("Can't seek")?;
+            reauth(spotify, || spotify.seek_track(position, Some(&device)))
+                .await
+                .map_err(|err| CommandError::failed("Could not seek", err))?;
+            Ok(Reply::Message(format!(
+                "Skipped to {}",
+                format_duration(position)
+            )))
+        }
+        Command::Volume(percent) => {
+            let device = session.require_device("Can't change the volume")?;
+            reauth(spotify, || spotify.volume(percent, Some(&device)))
+                .await
+                .map_err(|err| CommandError::failed("Could not change the volume", err))?;
+            Ok(Reply::Message(format!("Volume set to {}%", percent)))
+        }
+        Command::Shuffle(state) => {
+            let device = session.require_device("Can't change shuffle")?;
+            let state = match state {
+                Some(state) => state,
+                None => !session.currently_playing(spotify).await?.shuffle,
+            };
+            reauth(spotify, || spotify.shuffle(state, Some(&device)))
+                .await
+                .map_err(|err| CommandError::failed("Could not change shuffle", err))?;
+            Ok(Reply::Message(format!(
+                "Shuffle {}",
+                if state { "on" } else { "off" }
+            )))
+        }
+        Command::Queue(None) => {
+            let queue = get_queue(spotify).await?;
+            Ok(Reply::data(list_queue(&queue), queue))
+        }
+        Command::Queue(Some(query)) => queue_song(spotify, &query, &session.active_device).await,
+        Command::Status { follow: true, .. } => Err(CommandError::Usage(
+            "status --follow only works from the command line".to_string(),
+        )),
+        Command::Status {
+            format: Some(format),
+            ..
+        } => {
+            let state = get_player_state(spotify).await?;
+            let text = render(&format, &state).map_err(CommandError::Usage)?;
+            Ok(Reply::data(text, state))
+        }
+        Command::Status { json, .. } => {
+            let currently_playing = session.now_playing(spotify).await?;
+            Ok(Reply::data(
+                status(&currently_playing, json),
+                currently_playing,
+            ))
+        }
+        Command::Watch => watch(spotify, session).await,
+        // it runs other commands, the repl and the cli start it themselves
+        Command::Keys => Err(CommandError::Usage(
+            "keys only works in the repl and from the command line".to_string(),
+        )),
+        Command::Whoami => current_user(spotify).await,
+        Command::Logout { all: false } => {
+            logout(&session.profile_name)
+                .await
+                .map_err(|err| CommandError::failed("Could not log out", err))?;
+            Ok(Reply::Exit(Some(format!(
+                "Logged out of {}",
+                session.profile_name
+            ))))
+        }
+        Command::Logout { all: true } => {
+            logout_all()
+                .await
+                .map_err(|err| CommandError::failed("Could not log out", err))?;
+            Ok(Reply::Exit(Some("Logged out of every profile".to_string())))
+        }
+        Command::Credentials(action) => credentials_command(&session.profile_name, action)
+            .await
+            .map(Reply::Message),
+        Command::Profile(name) => select_profile(&session.profile_name, name.as_deref()),
+        Command::Exit => {
+            // nothing to ask with prompts off, ex: in the tui
+            if !can_prompt() || you_can_not_leave() {
+                return Ok(Reply::Exit(None));
+            }
+            Ok(Reply::Done)
+        }
+    }
+}
+
+async fn pause(spotify: &impl Authorize, device: &str) -> Result<Reply, CommandError> {
+    reauth(spotify, || spotify.pause_playback(Some(device)))
+        .await
+        .map_err(|err| CommandError::failed("Could not pause playback", err))?;
+    Ok(Reply::Message("Paused playback".to_string()))
+}
+
+async fn resume(spotify: &impl Authorize, device: &str) -> Result<Reply, CommandError> {
+    reauth(spotify, || {
+        spotify.resume_playback(Some(device), Duration::zero().into())
+    })
+    .await
+    .map_err(|err| CommandError::failed("Could not resume playback", err))?;
+    Ok(Reply::Message("Resumed playback".to_string()))
+}
+
+pub fn status(currently_playing: &CurrentlyPlaying, json: bool) -> String {
+    if json {
+        return serde_json::to_string(currently_playing).unwrap();
+    }
+
+    if currently_playing.song_name.is_empty() {
+        return "You are not listening to anything at the moment".to_string();
+    }
+
+    // ex:
+    // ▶ Daft Punk - Harder, Better, Faster, Stronger (Discovery)
+    //   2:13 / 3:44 ━━━━━━━━━━━━━━━━━●────────────
+    //   from album Discovery · on Kitchen · shuffle off · repeat context
+    let progress = currently_playing.progress.unwrap_or_default();
+    let mut text = format!(
+        "{} {} - {}",
+        if currently_playing.is_playing {
+            "▶"
+        } else {
+            "⏸"
+        },
+        currently_playing.artists.join(", "),
+        currently_playing.song_name.bold()
+    );
+    if !currently_playing.album.is_empty() {
+        text += &format!(" ({})", currently_playing.album);
+    }
+
+    text += &format!(
+        "\n  {} / {} {}",
+        format_duration(progress),
+        format_duration(currently_playing.duration),
+        progress_bar(progress, currently_playing.duration, STATUS_BAR_WIDTH)
+    );
+
+    text += &format!("\n  {}", playing_details(currently_playing));
+
+    text
+}
+
+// from album Discovery · on Kitchen · shuffle off · repeat context
+pub fn playing_details(currently_playing: &CurrentlyPlaying) -> String {
+    let mut details = vec![];
+    if let Some(context) = &currently_playing.context {
+        details.push(match (context.kind, &context.name) {
+            ("collection", Some(name)) => format!("from {}", name),
+            (kind, Some(name)) => format!("from {} {}", kind, name),
+            (kind, None) => format!("from a {}", kind),
+        });
+    }
+    if let Some(device) = &currently_playing.device {
+        details.push(format!("on {}", device.name));
+    }
+    details.push(format!(
+        "shuffle {}",
+        if currently_playing.shuffle {
+            "on"
+        } else {
+            "off"
+        }
+    ));
+    details.push(format!("repeat {}", currently_playing.repeat));
+
+    details.join(" · ")
+}
+
+const STATUS_BAR_WIDTH: usize = 30;
+
+// what status --follow prints without --format, the progress would make it
+// print every second
+const FOLLOW_FORMAT: &str = "{icon} {artists} - {title}";
+
+// status --follow, prints a line every time the output changes for status bars
+// that read lines (waybar, i3blocks, tmux), until it's killed
+pub async fn follow_status(
+    spotify: &impl Authorize,
+    format: Option<&str>,
+    json: bool,
+    config: &EventsConfig,
+) -> Result<(), CommandError> {
+    let (poll_interval, idle_interval) = config.intervals();
+    let mut interval = poll_interval;
+    let mut last_line = None;
+
+    loop {
+        match get_player_state(spotify).await {
+            Ok(state) => {
+                let line = if json {
+                    serde_json::to_string(&state).unwrap()
+                } else {
+                    render(format.unwrap_or(FOLLOW_FORMAT), &state).map_err(CommandError::Usage)?
+                };
+
+                if last_line.as_ref() != Some(&line) {
+                    println!("{}", line);
+                    std::io::stdout().flush().unwrap();
+                    last_line = Some(line);
+                }
+
+                // slower while nothing plays
+                interval = if state.is_playing {
+                    poll_interval
+                } else {
+                    (interval * 2).min(idle_interval)
+                };
+            }
+            Err(err) => {
+                eprint_err(&err.to_string());
+                interval = (interval * 2).min(idle_interval);
+            }
+        }
+
+        sleep(interval).await;
+    }
+}
@@ -0,0 +1,147 @@
+use colored::Colorize;
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    terminal,
+};
+use std::{collections::HashMap, time::Duration};
+use terminal_spotify::{can_prompt, print_err};
+use tokio::time::sleep;
+
+use crate::{
+    auth::Authorize,
+    command::{parse, Command, CommandError, Reply},
+    config::KeysConfig,
+    handlers::{execute, Session},
+};
+
+///// HOTKEYS /////
+// `keys`, every key press runs an action right away without enter. only the
+// waiting for a key happens in raw mode, the actions run like repl commands so
+// their output and prompts (the search on /) look the same
+
+const KEY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub async fn run(
+    spotify: &mut impl Authorize,
+    session: &mut Session,
+    config: &KeysConfig,
+) -> Result<Reply, CommandError> {
+    if !can_prompt() {
+        return Err(CommandError::Usage("keys needs a terminal".to_string()));
+    }
+
+    let keymap = config.keymap();
+    println!("{}", help(&keymap).dimmed());
+
+    loop {
+        let key = match read_key().await {
+            Ok(key) => key,
+            Err(err) => return Err(CommandError::failed("Could not read the keyboard", err)),
+        };
+        // raw mode doesn't turn it into a signal
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Ok(Reply::Done);
+        }
+        let name = match key_name(&key) {
+            Some(name) => name,
+            None => continue,
+        };
+        let action = match keymap.get(&name) {
+            Some(action) => action.as_str(),
+            None => continue,
+        };
+
+        let command = match action {
+            "quit" => return Ok(Reply::Done),
+            "seek_forward" => Command::Forward(Some(config.seek_step)),
+            "seek_back" => Command::Back(Some(config.seek_step)),
+            "volume_up" | "volume_down" => match session.currently_playing(spotify).await {
+                Ok(playing) => {
+                    let volume = match playing.volume {
+                        Some(volume) => volume as i64,
+                        None => {
+                            print_err("The device doesn't say what its volume is");
+                            continue;
+                        }
+                    };
+                    let step = config.volume_step as i64;
+                    let volume = match action {
+                        "volume_up" => volume + step,
+                        _ => volume - step,
+                    };
+                    Command::Volume(volume.clamp(0, 100) as u8)
+                }
+                Err(err) => {
+                    print_err(&err.to_string());
+                    continue;
+                }
+            },
+            line => match parse(line) {
+                Ok(command) => command,
+                Err(err) => {
+                    print_err(&format!("{} is bound to {}: {}", name, line, err));
+                    continue;
+                }
+            },
+        };
+
+        match execute(spotify, session, command).await {
+            Ok(Reply::Done) => (),
+            Ok(Reply::Message(message)) | Ok(Reply::Data { text: message, .. }) => {
+                println!("{}", message)
+            }
+            // exit and profile go back to the repl to do what they do
+            Ok(reply) => return Ok(reply),
+            Err(err) => print_err(&err.to_string()),
+        }
+    }
+}
+
+// raw mode only for as long as it waits
+async fn read_key() -> std::io::Result<KeyEvent> {
+    terminal::enable_raw_mode()?;
+    let key = wait_for_key().await;
+    terminal::disable_raw_mode()?;
+    key
+}
+
+async fn wait_for_key() -> std::io::Result<KeyEvent> {
+    loop {
+        if !event::poll(Duration::ZERO)? {
+            sleep(KEY_POLL_INTERVAL).await;
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press {
+                return Ok(key);
+            }
+        }
+    }
+}
+
+// the name a key has in the keymap
+fn key_name(key: &KeyEvent) -> Option<String> {
+    let name = match key.code {
+        KeyCode::Char(' ') => "space",
+        KeyCode::Char(c) => return Some(c.to_string()),
+        KeyCode::Enter => "enter",
+        KeyCode::Tab => "tab",
+        KeyCode::Esc => "esc",
+        KeyCode::Left => "left",
+        KeyCode::Right => "right",
+        KeyCode::Up => "up",
+        KeyCode::Down => "down",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+// space: p · n: next · .. sorted so it's the same every time
+fn help(keymap: &HashMap<String, String>) -> String {
+    let mut bindings: Vec<String> = keymap
+        .iter()
+        .map(|(key, action)| format!("{}: {}", key, action))
+        .collect();
+    bindings.sort();
+    bindings.join(" · ")
+}
@@ -0,0 +1,377 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, Request, State,
+    },
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response as HttpResponse},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::{fs, io::Read, io::Write, sync::Arc};
+use terminal_spotify::eprint_err;
+use tokio::{
+    net::TcpListener,
+    sync::broadcast::{self, error::RecvError},
+};
+
+use crate::{
+    auth::{ensure_scopes, Authorize},
+    command::{Command, CommandError, MAX_SEEK_SECONDS},
+    config::{config_dir, HttpConfig},
+    daemon::{run_request, SharedSession},
+    events::Event,
+    find_active_device, find_albums, find_songs, get_available_devices, get_queue,
+    output::Response,
+    to_devices,
+};
+
+///// REST API /////
+// served by the daemon next to the socket, every request needs
+// `Authorization: Bearer <token>`, or `?token=<token>` for the /events
+// websocket since browsers can't set headers there
+//
+// GET  /status                      what is playing
+// GET  /devices                     available devices
+// POST /play {"query":"..."}        resume, or search and play like the play command
+// POST /pause, /next, /prev
+// POST /seek {"position_ms":90000}
+// POST /volume {"percent":40}
+// GET  /search?q=...&type=track     type is track or album
+// GET  /queue                       what is up next
+// POST /queue {"query":"..."}       search for a song and queue it
+// GET  /events                      websocket, one json object per player state change
+
+const TOKEN_FILE: &str = "http_token";
+
+pub async fn bind(config: &HttpConfig) -> Result<TcpListener, Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(&config.address).await?;
+    let address = listener.local_addr()?;
+
+    if !address.ip().is_loopback() {
+        eprint_err(&format!(
+            "The rest api can be reached from other machines on {}",
+            address
+        ));
+    }
+    println!("Serving the rest api on http://{}", address);
+
+    Ok(listener)
+}
+
+pub async fn serve<S: Authorize + Send + 'static>(
+    listener: TcpListener,
+    config: HttpConfig,
+    state: SharedSession<S>,
+    events: broadcast::Sender<Event>,
+) {
+    let token = match config.token {
+        Some(token) => token,
+        None => match saved_token() {
+            Ok(token) => token,
+            Err(err) => {
+                eprint_err(&format!(
+                    "Could not create a token for the rest api: {}",
+                    err
+                ));
+                return;
+            }
+        },
+    };
+
+    let app = Router::new()
+        .route("/status", get(status::<S>))
+        .route("/devices", get(devices::<S>))
+        .route("/play", post(play::<S>))
+        .route("/pause", post(pause::<S>))
+        .route("/next", post(next::<S>))
+        .route("/prev", post(prev::<S>))
+        .route("/seek", post(seek::<S>))
+        .route("/volume", post(volume::<S>))
+        .route("/search", get(search::<S>))
+        .route("/queue", get(queue::<S>).post(add_to_queue::<S>))
+        .route("/events", get(subscribe))
+        .route_layer(middleware::from_fn_with_state(Arc::new(token), authorize))
+        .layer(Extension(events))
+        .with_state(state);
+
+    if let Err(err) = axum::serve(listener, app).await {
+        eprint_err(&format!("The rest api stopped: {}", err));
+    }
+}
+
+// the token from config_dir/http_token, made the first time it's needed
+fn saved_token() -> std::io::Result<String> {
+    let path = config_dir().join(TOKEN_FILE);
+    if let Ok(token) = fs::read_to_string(&path) {
+        if !token.trim().is_empty() {
+            return Ok(token.trim().to_string());
+        }
+    }
+
+    let mut bytes = [0u8; 32];
+    fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    fs::create_dir_all(config_dir())?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(&path)?.write_all(token.as_bytes())?;
+
+    println!("Created a token for the rest api in {}", path.display());
+    Ok(token)
+}
+
+async fn authorize(State(token): State<Arc<String>>, request: Request, next: Next) -> HttpResponse {
+    let given = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        // only the websocket takes it from the query, urls end up in logs
+        .or_else(|| {
+            if request.uri().path() != "/events" {
+                return None;
+            }
+            request
+                .uri()
+                .query()?
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("token="))
+        });
+
+    match given {
+        Some(given) if same_token(given.trim(), &token) => next.run(request).await,
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            Json(Response {
+                ok: false,
+                message: None,
+                data: None,
+                error: Some("Missing or wrong bearer token".to_string()),
+                code: 6,
+            }),
+        )
+            .into_response(),
+    }
+}
+
+// compares every byte so the time it takes doesn't give the token away
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+///// RESPONSES /////
+fn status_code(code: i32) -> StatusCode {
+    match code {
+        0 => StatusCode::OK,
+        2 | 5 => StatusCode::BAD_REQUEST,
+        3 => StatusCode::CONFLICT,
+        4 => StatusCode::NOT_FOUND,
+        6 => StatusCode::FORBIDDEN,
+        _ => StatusCode::BAD_GATEWAY,
+    }
+}
+
+fn json<T: Serialize>(res: Result<T, CommandError>) -> HttpResponse {
+    match res {
+        Ok(value) => Json(value).into_response(),
+        Err(err) => {
+            let response = Response::err(err);
+            (status_code(response.code), Json(response)).into_response()
+        }
+    }
+}
+
+// runs a command like the socket does, ex: "seek 90"
+async fn command<S: Authorize>(state: &SharedSession<S>, words: Vec<String>) -> HttpResponse {
+    let (response, _) = run_request(state, &shell_words::join(words)).await;
+    (status_code(response.code), Json(response)).into_response()
+}
+
+///// HANDLERS /////
+async fn status<S: Authorize>(State(state): State<SharedSession<S>>) -> HttpResponse {
+    let mut state = state.lock().await;
+    let (spotify, session) = &mut *state;
+
+    if let Err(err) = ensure_scopes(
+        spotify,
+        Command::Status {
+            json: true,
+            format: None,
+            follow: false,
+        }
+        .scopes(),
+    )
+    .await
+    {
+        return json::<()>(Err(err));
+    }
+    json(session.now_playing(spotify).await)
+}
+
+async fn devices<S: Authorize>(State(state): State<SharedSession<S>>) -> HttpResponse {
+    let mut state = state.lock().await;
+    let (spotify, session) = &mut *state;
+
+    if let Err(err) = ensure_scopes(spotify, Command::Devices.scopes()).await {
+        return json::<()>(Err(err));
+    }
+
+    let devices = match get_available_devices(spotify).await {
+        Ok(devices) => devices,
+        Err(err) => return json::<()>(Err(err)),
+    };
+    session.active_device = find_active_device(&devices);
+    session.devices = devices;
+
+    json(Ok(to_devices(&session.devices)))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct QueryBody {
+    query: Option<String>,
+}
+
+async fn play<S: Authorize>(
+    State(state): State<SharedSession<S>>,
+    body: Option<Json<QueryBody>>,
+) -> HttpResponse {
+    let mut words = vec!["play".to_string()];
+    if let Some(Json(QueryBody { query: Some(query) })) = body {
+        words.push(query);
+    }
+    command(&state, words).await
+}
+
+async fn pause<S: Authorize>(State(state): State<SharedSession<S>>) -> HttpResponse {
+    command(&state, vec!["pause".to_string()]).await
+}
+
+async fn next<S: Authorize>(State(state): State<SharedSession<S>>) -> HttpResponse {
+    command(&state, vec!["next".to_string()]).await
+}
+
+async fn prev<S: Authorize>(State(state): State<SharedSession<S>>) -> HttpResponse {
+    command(&state, vec!["prev".to_string()]).await
+}
+
+#[derive(Debug, Deserialize)]
+struct SeekBody {
+    position_ms: i64,
+}
+
+async fn seek<S: Authorize>(
+    State(state): State<SharedSession<S>>,
+    Json(body): Json<SeekBody>,
+) -> HttpResponse {
+    if !(0..=MAX_SEEK_SECONDS * 1000).contains(&body.position_ms) {
+        return json::<()>(Err(CommandError::Usage(format!(
+            "position_ms has to be between 0 and {}",
+            MAX_SEEK_SECONDS * 1000
+        ))));
+    }
+    let seconds = body.position_ms / 1000;
+    command(&state, vec!["seek".to_string(), seconds.to_string()]).await
+}
+
+#[derive(Debug, Deserialize)]
+struct VolumeBody {
+    percent: u8,
+}
+
+async fn volume<S: Authorize>(
+    State(state): State<SharedSession<S>>,
+    Json(body): Json<VolumeBody>,
+) -> HttpResponse {
+    command(&state, vec!["volume".to_string(), body.percent.to_string()]).await
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchParams {
+    q: String,
+    #[serde(rename = "type", default)]
+    kind: Option<String>,
+}
+
+async fn search<S: Authorize>(
+    State(state): State<SharedSession<S>>,
+    Query(params): Query<SearchParams>,
+) -> HttpResponse {
+    let state = state.lock().await;
+    let (spotify, _) = &*state;
+
+    match params.kind.as_deref().unwrap_or("track") {
+        "track" => json(Ok(find_songs(spotify, &params.q).await)),
+        "album" => json(Ok(find_albums(spotify, &params.q).await)),
+        kind => json::<()>(Err(CommandError::Usage(format!(
+            "Can only search for track or album, not {}",
+            kind
+        )))),
+    }
+}
+
+async fn queue<S: Authorize>(State(state): State<SharedSession<S>>) -> HttpResponse {
+    let mut state = state.lock().await;
+    let (spotify, _) = &mut *state;
+
+    if let Err(err) = ensure_scopes(spotify, Command::Queue(None).scopes()).await {
+        return json::<()>(Err(err));
+    }
+    json(get_queue(spotify).await)
+}
+
+async fn add_to_queue<S: Authorize>(
+    State(state): State<SharedSession<S>>,
+    Json(body): Json<QueryBody>,
+) -> HttpResponse {
+    match body.query {
+        Some(query) => command(&state, vec!["queue".to_string(), query]).await,
+        None => json::<()>(Err(CommandError::Usage(
+            "Give a query for the song to queue".to_string(),
+        ))),
+    }
+}
+
+///// EVENTS /////
+async fn subscribe(
+    ws: WebSocketUpgrade,
+    Extension(events): Extension<broadcast::Sender<Event>>,
+) -> HttpResponse {
+    ws.on_upgrade(move |socket| send_events(socket, events.subscribe()))
+}
+
+async fn send_events(mut socket: WebSocket, mut events: broadcast::Receiver<Event>) {
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let json = serde_json::to_string(&event).unwrap();
+                    if socket.send(Message::Text(json)).await.is_err() {
+                        return;
+                    }
+                }
+                // too slow to keep up, skip what was missed
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                // nothing is expected from the client
+                Some(Ok(_)) => (),
+            },
+        }
+    }
+}
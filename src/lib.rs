@@ -1,8 +1,12 @@
+use chrono::TimeDelta;
 use colored::Colorize;
+use dialoguer::Select;
+use serde::Serializer;
 use std::{
     env,
     fmt::Display,
-    io::{self, Write},
+    io::{self, IsTerminal, Write},
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 ///// FUNCTIONS /////
@@ -15,7 +19,7 @@ pub fn get_env(variable: &str) -> String {
             return String::from("");
         }
     };
-    return env_var.to_string();
+    env_var.to_string()
 }
 
 pub fn user_input() -> String {
@@ -26,11 +30,85 @@ pub fn user_input() -> String {
         Ok(input)
     }
 
-    let input = read_input();
+    read_input().unwrap_or_default()
+}
+
+static PROMPTS_DISABLED: AtomicBool = AtomicBool::new(false);
 
-    match input {
-        Ok(input) => input,
-        Err(_) => String::new(),
+// prompts need someone at a terminal to answer them, scripts and keybindings
+// get an error instead of a prompt that hangs forever
+pub fn can_prompt() -> bool {
+    !PROMPTS_DISABLED.load(Ordering::Relaxed) && io::stdin().is_terminal()
+}
+
+pub fn disable_prompts() {
+    PROMPTS_DISABLED.store(true, Ordering::Relaxed)
+}
+
+// turns prompts back on when dropped, for the tui which only needs them off
+// while it is on screen
+pub struct PromptsDisabled;
+
+impl Drop for PromptsDisabled {
+    fn drop(&mut self) {
+        PROMPTS_DISABLED.store(false, Ordering::Relaxed)
+    }
+}
+
+pub fn disable_prompts_for_now() -> PromptsDisabled {
+    disable_prompts();
+    PromptsDisabled
+}
+
+// None if there is no one to ask
+pub fn select<T: ToString>(prompt: &str, items: &[T]) -> Option<usize> {
+    if !can_prompt() {
+        return None;
+    }
+
+    Some(
+        Select::new()
+            .with_prompt(prompt)
+            .items(items)
+            .interact()
+            .unwrap(),
+    )
+}
+
+// minutes:seconds, ex: 16:47
+pub fn format_duration(duration: TimeDelta) -> String {
+    let seconds = duration.num_seconds().max(0);
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+// ex: ━━━━━━━━━●──────────────────── a third of the way in
+pub fn progress_bar(progress: TimeDelta, duration: TimeDelta, width: usize) -> String {
+    let width = width.max(1);
+    let ratio = if duration > TimeDelta::zero() {
+        (progress.num_milliseconds() as f64 / duration.num_milliseconds() as f64).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let filled = ((width - 1) as f64 * ratio).round() as usize;
+
+    format!("{}●{}", "━".repeat(filled), "─".repeat(width - 1 - filled))
+}
+
+// durations are milliseconds in json, like in the spotify api
+pub fn serialize_millis<S: Serializer>(
+    duration: &TimeDelta,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_i64(duration.num_milliseconds())
+}
+
+pub fn serialize_millis_opt<S: Serializer>(
+    duration: &Option<TimeDelta>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => serialize_millis(duration, serializer),
+        None => serializer.serialize_none(),
     }
 }
 
@@ -50,6 +128,20 @@ pub fn print_err(text: &str) {
     println!("{}", text.bold().bright_red())
 }
 
+// for errors that shouldn't end up in the output of a script
+pub fn eprint_err(text: &str) {
+    eprintln!("{}", text.bold().bright_red())
+}
+
+// eprint_err with an extra argument
+pub fn eprintf_err<T: Display>(text: &str, err: T) {
+    eprintln!(
+        "{}: {}",
+        text.bold().bright_red(),
+        err.to_string().bold().bright_red()
+    )
+}
+
 // printf from go, can accept an extra argument
 pub fn printf_err<T: Display>(text: &str, err: T) {
     println!(
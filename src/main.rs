@@ -1,46 +1,57 @@
-use chrono::{Duration, TimeDelta};
+use chrono::TimeDelta;
 use colored::Colorize;
-use dialoguer::Select;
 use dotenv::dotenv;
 use rspotify::{
-    model::{AlbumId, PlayableItem, PlaylistId, SearchResult, SearchType, TrackId},
+    model::{
+        idtypes::parse_uri, AdditionalType, AlbumId, ArtistId, Offset, PlayableItem, PlaylistId,
+        RepeatState, SearchResult, SearchType, ShowId, TrackId, Type,
+    },
     prelude::*,
-    scopes, AuthCodeSpotify, Credentials, OAuth,
+    AuthCodePkceSpotify, AuthCodeSpotify, ClientCredsSpotify,
+};
+use serde::Serialize;
+use serde_json::json;
+use terminal_spotify::{
+    can_prompt, eprint_err, format_duration, print_err, select, serialize_millis,
+    serialize_millis_opt,
 };
-use std::io::Write;
-use terminal_spotify::{get_env, print_err, printf_err, user_input, you_can_not_leave};
-
-// has to be &str can't call String::from outside fn ?
-const REDIRECT_URI: &str = "http://localhost:8888/callback";
-
-////// AUTH /////
-// TODO: IMPROVE AUTH (store refresh token in db or locally?)
-async fn authorize_user(
-    client_id: &str,
-    client_secret: &str,
-) -> Result<AuthCodeSpotify, Box<dyn std::error::Error>> {
-    let creds = Credentials::new(client_id, client_secret);
-    let oauth = OAuth {
-        redirect_uri: REDIRECT_URI.to_string(),
-        scopes: scopes!(
-            "user-read-playback-state",    // get status about player
-            "user-read-currently-playing", // get status about player
-            "user-modify-playback-state",  // interact with player
-            "playlist-read-private"        // to get playlists
-        ),
-        ..Default::default()
-    };
-
-    let spotify = AuthCodeSpotify::new(creds, oauth);
-
-    let url = spotify.get_authorize_url(false)?;
-    spotify.prompt_for_token(&url).await?;
 
-    Ok(spotify)
-}
+mod alias;
+mod auth;
+mod browse;
+mod callback;
+mod cli;
+mod command;
+mod config;
+mod credentials;
+mod daemon;
+mod editor;
+mod events;
+mod handlers;
+mod hotkeys;
+mod http;
+mod mpris;
+mod notify;
+mod output;
+mod scopes;
+mod scrobble;
+mod template;
+mod tui;
+mod watch;
+use auth::{
+    authorize_client_creds, authorize_user, authorize_user_pkce, ensure_scopes, granted_scopes,
+    reauth, Authorize,
+};
+use cli::{LoginFlags, Mode};
+use command::{parse_input, Command, CommandError, Reply, MAX_SEEK_SECONDS};
+use config::{check_profile_name, AppConfig, Profile, DEFAULT_PROFILE};
+use credentials::client_secret;
+use editor::{remember_search, HistoryKind, LineEditor};
+use handlers::{execute, follow_status, Session};
+use output::{print_result, Output};
 
 ///// DEVICES /////
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 struct Device {
     id: String,
     name: String,
@@ -56,9 +67,14 @@ impl Default for Device {
     }
 }
 
-async fn get_available_devices(spotify: &AuthCodeSpotify) -> Vec<(String, String, bool)> {
-    let devices = spotify.device().await.unwrap();
-    devices
+async fn get_available_devices(
+    spotify: &impl Authorize,
+) -> Result<Vec<(String, String, bool)>, CommandError> {
+    let devices = reauth(spotify, || spotify.device())
+        .await
+        .map_err(|err| CommandError::failed("Could not get the available devices", err))?;
+
+    Ok(devices
         .iter()
         .map(|device| {
             (
@@ -67,117 +83,333 @@ async fn get_available_devices(spotify: &AuthCodeSpotify) -> Vec<(String, String
                 device.is_active,
             )
         })
+        .collect())
+}
+
+fn to_devices(devices: &[(String, String, bool)]) -> Vec<Device> {
+    devices
+        .iter()
+        .map(|(id, name, is_active)| Device {
+            id: id.clone(),
+            name: name.clone(),
+            is_active: *is_active,
+        })
         .collect()
 }
 
-fn print_devices(devices: &Vec<(String, String, bool)>, active_device: &mut Device) {
-    if devices.len() == 0 {
-        print_err("No devices available currently");
-        return;
+// the device spotify says is active, an empty one if there is none
+fn find_active_device(devices: &[(String, String, bool)]) -> Device {
+    match devices
+        .iter()
+        .find(|(id, _, is_active)| *is_active && !id.is_empty())
+    {
+        Some((id, name, is_active)) => Device {
+            id: id.clone(),
+            name: name.clone(),
+            is_active: *is_active,
+        },
+        None => Device {
+            ..Default::default()
+        },
+    }
+}
+
+// lists the devices and sets active_device
+fn list_devices(
+    devices: &[(String, String, bool)],
+    active_device: &mut Device,
+) -> Result<String, CommandError> {
+    if devices.is_empty() {
+        return Err(CommandError::NotFound(
+            "No devices available currently".to_string(),
+        ));
     }
 
-    println!("Available devices:");
+    *active_device = find_active_device(devices);
 
-    for (id, name, is_active) in devices {
-        println!("Device name: {}, Active: {}", name, is_active);
-        if *is_active && id != "" {
-            *active_device = Device {
-                id: id.clone(),
-                name: name.clone(),
-                is_active: *is_active,
-            }
-        }
+    let mut list = String::from("Available devices:");
+    for (_, name, is_active) in devices {
+        list += &format!("\nDevice name: {}, Active: {}", name, is_active);
     }
 
-    if active_device.id == "" {
-        *active_device = Device {
-            ..Default::default()
-        }
-    }
+    Ok(list)
 }
 
+// activates the device with the given name, or asks which one to activate
 async fn activate_device(
-    spotify: &AuthCodeSpotify,
-    devices: &Vec<(String, String, bool)>,
+    spotify: &impl Authorize,
+    devices: &[(String, String, bool)],
+    name: Option<&str>,
     active_device: &mut Device,
-) {
-    let device_names: Vec<&str> = devices.iter().map(|device| (device.1.as_str())).collect();
-
-    let selection = Select::new()
-        .with_prompt("Choose the device you want to activate")
-        .items(&device_names[..])
-        .interact()
-        .unwrap();
-
-    for device in devices {
-        if device_names[selection] == device.1 {
-            *active_device = Device {
-                id: device.0.clone(),
-                name: device.1.clone(),
-                is_active: true,
+) -> Result<String, CommandError> {
+    let device = match name {
+        Some(name) => devices
+            .iter()
+            .find(|device| device.1.to_lowercase() == name.to_lowercase())
+            .ok_or_else(|| CommandError::NotFound(format!("Device is not available: {}", name)))?,
+        None => {
+            if devices.is_empty() {
+                return Err(CommandError::NotFound(
+                    "No devices available currently".to_string(),
+                ));
             }
+
+            let device_names: Vec<&str> = devices.iter().map(|device| device.1.as_str()).collect();
+
+            let selection = select("Choose the device you want to activate", &device_names)
+                .ok_or(CommandError::NeedsInput("Give the name of the device"))?;
+
+            &devices[selection]
         }
-    }
+    };
+
+    reauth(spotify, || {
+        spotify.transfer_playback(&device.0, Some(false))
+    })
+    .await
+    .map_err(|err| CommandError::failed("Could not activate the device", err))?;
 
-    match spotify
-        .transfer_playback(&active_device.id, Some(false))
+    *active_device = Device {
+        id: device.0.clone(),
+        name: device.1.clone(),
+        is_active: true,
+    };
+    Ok(format!("{} was activated", device.1))
+}
+
+///// USER /////
+async fn current_user(spotify: &impl Authorize) -> Result<Reply, CommandError> {
+    let user = reauth(spotify, || spotify.current_user())
         .await
-    {
-        Ok(_) => println!("{} was activated", device_names[selection]),
-        Err(err) => printf_err("Could not activate the device", err),
+        .map_err(|err| CommandError::failed("Could not get the current user", err))?;
+
+    let mut scopes: Vec<String> = granted_scopes(spotify).await.into_iter().collect();
+    scopes.sort();
+
+    let country = user.country.map(<&str>::from);
+    let product = user.product.map(<&str>::from);
+    let text = format!(
+        "Logged in as {} ({})\nCountry: {}\nSubscription: {}\nGranted scopes: {}",
+        user.display_name.as_deref().unwrap_or("no display name"),
+        user.id.id(),
+        country.unwrap_or("unknown"),
+        product.unwrap_or("unknown"),
+        scopes.join(", ")
+    );
+
+    Ok(Reply::data(
+        text,
+        json!({
+            "id": user.id.id(),
+            "display_name": user.display_name,
+            "country": country,
+            "product": product,
+            "scopes": scopes,
+        }),
+    ))
+}
+
+///// PROFILES /////
+// switches to the given profile, asks which one if no name is given
+fn select_profile(current_profile: &str, name: Option<&str>) -> Result<Reply, CommandError> {
+    let config =
+        AppConfig::load().map_err(|err| CommandError::failed("Could not load config", err))?;
+
+    let profile_names = config.profile_names();
+
+    if let Some(name) = name {
+        check_profile_name(name).map_err(CommandError::Usage)?;
     }
+
+    let next_profile = match name {
+        Some(name) if config.profile(name).is_some() => name.to_string(),
+        Some(name) => {
+            return Err(CommandError::NotFound(format!(
+                "Profile was not found in the config: {}",
+                name
+            )))
+        }
+        None => {
+            let selection = select(
+                &format!("Choose a profile (current: {})", current_profile),
+                &profile_names,
+            )
+            .ok_or(CommandError::NeedsInput("Give the name of the profile"))?;
+
+            profile_names[selection].clone()
+        }
+    };
+
+    if next_profile == current_profile {
+        return Ok(Reply::Message(format!("Already using {}", current_profile)));
+    }
+
+    Ok(Reply::SwitchProfile(next_profile))
 }
 
 ///// CURRENTLY PLAYING /////
-#[derive(Debug)]
+#[derive(Debug, Default, Clone, Serialize)]
 struct CurrentlyPlaying {
     is_playing: bool,
+    #[serde(rename = "progress_ms", serialize_with = "serialize_millis_opt")]
     progress: Option<TimeDelta>,
+    #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+    duration: TimeDelta,
     song_name: String,
     artists: Vec<String>,
+    // the show for episodes
+    album: String,
+    // the album or playlist it plays from
+    context: Option<PlayingContext>,
+    device: Option<Device>,
+    // of the device, in percent
+    volume: Option<u32>,
+    shuffle: bool,
+    // off, track or context
+    repeat: String,
 }
-async fn get_currently_playing(
-    spotify: &AuthCodeSpotify,
-) -> Result<CurrentlyPlaying, Box<dyn std::error::Error>> {
+
+impl CurrentlyPlaying {
+    // where the progress is by now, if it kept playing
+    fn moved_along(&self, elapsed: std::time::Duration) -> CurrentlyPlaying {
+        let mut playing = self.clone();
+        if playing.is_playing {
+            playing.progress = playing.progress.map(|progress| {
+                (progress + TimeDelta::from_std(elapsed).unwrap_or_default()).min(playing.duration)
+            });
+        }
+        playing
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct PlayingContext {
+    // album, playlist, artist, show or collection (liked songs)
+    kind: &'static str,
+    uri: String,
+    // spotify only gives the uri, playlists, artists and shows are looked up
+    // by Session::name_context
+    name: Option<String>,
+}
+
+async fn get_currently_playing(spotify: &impl Authorize) -> Result<CurrentlyPlaying, CommandError> {
     //fetch
-    let currently_playing = spotify.current_user_playing_item().await?.unwrap();
-
-    let currently_playing_data = CurrentlyPlaying {
-        is_playing: currently_playing.is_playing,
-        progress: currently_playing.progress,
-        song_name: match currently_playing.clone().item.unwrap() {
-            PlayableItem::Track(track) => track.name,
-            PlayableItem::Episode(episode) => episode.name,
+    let playback = reauth(spotify, || {
+        spotify.current_playback(None, Some([&AdditionalType::Episode]))
+    })
+    .await
+    .map_err(|err| CommandError::failed("Could not get the currently playing track", err))?;
+
+    // nothing has been played for a while
+    let (playback, item) = match playback {
+        Some(playback) => match playback.item.clone() {
+            Some(item) => (playback, item),
+            None => return Ok(CurrentlyPlaying::default()),
         },
-        artists: match currently_playing.item.unwrap() {
-            PlayableItem::Track(track) => track
+        None => return Ok(CurrentlyPlaying::default()),
+    };
+
+    let mut currently_playing = CurrentlyPlaying {
+        is_playing: playback.is_playing,
+        progress: playback.progress,
+        device: Some(Device {
+            id: playback.device.id.unwrap_or_default(),
+            name: playback.device.name,
+            is_active: playback.device.is_active,
+        }),
+        volume: playback.device.volume_percent,
+        shuffle: playback.shuffle_state,
+        repeat: String::from(match playback.repeat_state {
+            RepeatState::Off => "off",
+            RepeatState::Track => "track",
+            RepeatState::Context => "context",
+        }),
+        context: playback.context.map(|context| PlayingContext {
+            kind: context._type.into(),
+            uri: context.uri,
+            name: None,
+        }),
+        ..Default::default()
+    };
+
+    match item {
+        PlayableItem::Track(track) => {
+            currently_playing.song_name = track.name;
+            currently_playing.artists = track
                 .artists
                 .iter()
-                .map(|artist| (artist.name.clone()))
-                .collect(),
-            PlayableItem::Episode(..) => vec![String::from("")],
-        },
-    };
+                .map(|artist| artist.name.clone())
+                .collect();
+            currently_playing.album = track.album.name;
+            currently_playing.duration = track.duration;
+        }
+        PlayableItem::Episode(episode) => {
+            currently_playing.song_name = episode.name;
+            currently_playing.artists = vec![episode.show.publisher];
+            currently_playing.album = episode.show.name;
+            currently_playing.duration = episode.duration;
+        }
+    }
 
-    Ok(currently_playing_data)
+    // the album is already known
+    if let Some(context) = &mut currently_playing.context {
+        match context.kind {
+            "album" => context.name = Some(currently_playing.album.clone()),
+            "collection" => context.name = Some(String::from("Liked Songs")),
+            _ => (),
+        }
+    }
+
+    Ok(currently_playing)
+}
+
+// the name of the playlist, artist or show something plays from
+async fn context_name(spotify: &impl Authorize, context: &PlayingContext) -> Option<String> {
+    match context.kind {
+        "playlist" => {
+            let id = PlaylistId::from_uri(&context.uri).ok()?;
+            reauth(spotify, || spotify.playlist(id.clone(), None, None))
+                .await
+                .ok()
+                .map(|playlist| playlist.name)
+        }
+        "artist" => {
+            let id = ArtistId::from_uri(&context.uri).ok()?;
+            reauth(spotify, || spotify.artist(id.clone()))
+                .await
+                .ok()
+                .map(|artist| artist.name)
+        }
+        "show" => {
+            let id = ShowId::from_uri(&context.uri).ok()?;
+            reauth(spotify, || spotify.get_a_show(id.clone(), None))
+                .await
+                .ok()
+                .map(|show| show.name)
+        }
+        _ => None,
+    }
 }
 
 ///// SEARCHING / SELECTING /////
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 struct SearchRes<'a> {
     id: TrackId<'a>,
     song_name: String,
     artists: Vec<String>,
+    album: String,
+    #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+    duration: TimeDelta,
 }
 
-async fn search_song<'a>(spotify: &AuthCodeSpotify, query: &str, active_device: &mut Device) {
-    let res = spotify
-        .search(query, SearchType::Track, None, None, Some(5), None)
-        .await;
+async fn find_songs(spotify: &impl BaseClient, query: &str) -> Vec<SearchRes<'static>> {
+    let res = reauth(spotify, || {
+        spotify.search(query, SearchType::Track, None, None, Some(5), None)
+    })
+    .await;
 
     // flat_map cause have to do 2 iterations
-    let search_data: Vec<SearchRes<'_>> = res
-        .iter()
+    res.iter()
         .flat_map(|result: &_| match result {
             SearchResult::Tracks(tracks) => tracks
                 .clone()
@@ -191,11 +423,25 @@ async fn search_song<'a>(spotify: &AuthCodeSpotify, query: &str, active_device:
                         .iter()
                         .map(|artist| artist.name.clone())
                         .collect(),
+                    album: track.album.name.clone(),
+                    duration: track.duration,
                 })
                 .collect::<Vec<SearchRes<'_>>>(),
             _ => vec![],
         })
-        .collect();
+        .collect()
+}
+
+// without a terminal to pick in the top result is played
+async fn search_song(
+    spotify: &impl Authorize,
+    query: &str,
+    active_device: &mut Device,
+) -> Result<Reply, CommandError> {
+    let search_data = find_songs(spotify, query).await;
+    if search_data.is_empty() {
+        return Err(CommandError::NotFound("No songs found".to_string()));
+    }
 
     let search_data_song_and_artists: Vec<String> = search_data
         .iter()
@@ -208,42 +454,41 @@ async fn search_song<'a>(spotify: &AuthCodeSpotify, query: &str, active_device:
         })
         .collect();
 
-    let selection = Select::new()
-        .with_prompt("Select song: ")
-        .items(&search_data_song_and_artists[..])
-        .interact()
-        .unwrap();
+    let selection = select("Select song: ", &search_data_song_and_artists).unwrap_or(0);
 
     let selected_song = &search_data[selection];
 
-    match spotify
-        .start_uris_playback(
+    reauth(spotify, || {
+        spotify.start_uris_playback(
             Some(PlayableId::from(selected_song.id.clone())),
             Some(&active_device.id),
             None,
             None,
         )
-        .await
-    {
-        Ok(_) => println!("Started playing: {}", selected_song.song_name),
-        Err(err) => printf_err("Could not start playing song", err),
-    }
+    })
+    .await
+    .map_err(|err| CommandError::failed("Could not start playing song", err))?;
+
+    Ok(Reply::data(
+        format!("Started playing: {}", selected_song.song_name),
+        json!({ "played": selected_song, "results": search_data }),
+    ))
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 struct Album<'a> {
     id: AlbumId<'a>,
     name: String,
     artists: Vec<String>,
 }
 
-async fn search_album(spotify: &AuthCodeSpotify, query: &str, active_device: &mut Device) {
-    let res = spotify
-        .search(query, SearchType::Album, None, None, Some(5), None)
-        .await;
+async fn find_albums(spotify: &impl BaseClient, query: &str) -> Vec<Album<'static>> {
+    let res = reauth(spotify, || {
+        spotify.search(query, SearchType::Album, None, None, Some(5), None)
+    })
+    .await;
 
-    let search_data: Vec<Album<'_>> = res
-        .iter()
+    res.iter()
         .flat_map(|result: &_| match result {
             SearchResult::Albums(albums) => albums
                 .clone()
@@ -261,45 +506,59 @@ async fn search_album(spotify: &AuthCodeSpotify, query: &str, active_device: &mu
                 .collect::<Vec<Album<'_>>>(),
             _ => vec![],
         })
-        .collect();
+        .collect()
+}
+
+async fn search_album(
+    spotify: &impl Authorize,
+    query: &str,
+    active_device: &mut Device,
+) -> Result<Reply, CommandError> {
+    let search_data = find_albums(spotify, query).await;
+    if search_data.is_empty() {
+        return Err(CommandError::NotFound("No albums found".to_string()));
+    }
 
     let search_data_album_and_artists: Vec<String> = search_data
         .iter()
         .map(|album| format!("{} - {}", album.name.as_str(), album.artists.join(", ")))
         .collect();
 
-    let selection = Select::new()
-        .with_prompt("Select song: ")
-        .items(&search_data_album_and_artists[..])
-        .interact()
-        .unwrap();
+    let selection = select("Select album: ", &search_data_album_and_artists).unwrap_or(0);
 
     let selected_album = &search_data[selection];
 
-    match spotify
-        .start_context_playback(
+    reauth(spotify, || {
+        spotify.start_context_playback(
             PlayContextId::from(selected_album.id.clone()),
             Some(&active_device.id),
             None,
             None,
         )
-        .await
-    {
-        Ok(_) => println!("Started playing: {}", selected_album.name),
-        Err(err) => printf_err("Could not start playing album", err),
-    }
+    })
+    .await
+    .map_err(|err| CommandError::failed("Could not start playing album", err))?;
+
+    Ok(Reply::data(
+        format!("Started playing: {}", selected_album.name),
+        json!({ "played": selected_album, "results": search_data }),
+    ))
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 struct Playlist<'a> {
     id: PlaylistId<'a>,
     name: String,
 }
 
-async fn select_playlist(spotify: &AuthCodeSpotify, active_device: &mut Device) {
-    let playlists = spotify.current_user_playlists_manual(Some(10), None).await;
+// the first 10 of the user's playlists
+async fn get_playlists(spotify: &impl Authorize) -> Vec<Playlist<'static>> {
+    let playlists = reauth(spotify, || {
+        spotify.current_user_playlists_manual(Some(10), None)
+    })
+    .await;
 
-    let playlist_data: Vec<Playlist<'_>> = playlists
+    playlists
         .iter()
         .flat_map(|res: &_| {
             res.clone()
@@ -311,238 +570,555 @@ async fn select_playlist(spotify: &AuthCodeSpotify, active_device: &mut Device)
                 })
                 .collect::<Vec<Playlist<'_>>>()
         })
+        .collect()
+}
+
+// plays one of the user's playlists, the name narrows down the choices
+async fn select_playlist(
+    spotify: &impl Authorize,
+    name: Option<&str>,
+    active_device: &mut Device,
+    playlist_names: &mut Vec<String>,
+) -> Result<Reply, CommandError> {
+    let playlists = get_playlists(spotify).await;
+    // keeps tab completion up to date
+    *playlist_names = playlists
+        .iter()
+        .map(|playlist| playlist.name.clone())
+        .collect();
+
+    let playlist_data: Vec<Playlist<'_>> = playlists
+        .into_iter()
+        .filter(|playlist| match name {
+            Some(name) => playlist.name.to_lowercase().contains(&name.to_lowercase()),
+            None => true,
+        })
         .collect();
 
+    if playlist_data.is_empty() {
+        return Err(CommandError::NotFound(match name {
+            Some(name) => format!("No playlist matches: {}", name),
+            None => "You don't have any playlists".to_string(),
+        }));
+    }
+
     let playlist_data_names: Vec<String> = playlist_data
         .iter()
         .map(|playlist| playlist.name.clone())
         .collect();
 
-    let selection = Select::new()
-        .with_prompt("Select song")
-        .items(&playlist_data_names[..])
-        .interact()
-        .unwrap();
+    let selection = match name {
+        // no need to ask if the name only matched one, or when there is no one
+        // to ask the exact match or the first one
+        Some(name) if playlist_data.len() == 1 || !can_prompt() => playlist_data
+            .iter()
+            .position(|playlist| playlist.name.to_lowercase() == name.to_lowercase())
+            .unwrap_or(0),
+        _ => select("Select playlist", &playlist_data_names)
+            .ok_or(CommandError::NeedsInput("Give the name of the playlist"))?,
+    };
 
-    match spotify
-        .start_context_playback(
+    reauth(spotify, || {
+        spotify.start_context_playback(
             PlayContextId::from(playlist_data[selection].id.clone()),
             Some(&active_device.id),
             None,
             None,
         )
-        .await
-    {
-        Ok(_) => println!(
+    })
+    .await
+    .map_err(|err| CommandError::failed("Could not start playing playlist", err))?;
+
+    Ok(Reply::data(
+        format!(
             "Started playing playlist: {}",
             playlist_data[selection].name
         ),
-        Err(err) => printf_err("Could not start playing playlist", err),
+        json!({ "played": playlist_data[selection], "playlists": playlist_data }),
+    ))
+}
+
+// play spotify:track:.. or spotify:album:.., an album, playlist or artist can
+// start from one of its tracks
+async fn play_uri(
+    spotify: &impl Authorize,
+    uri: &str,
+    offset: Option<&str>,
+    active_device: &Device,
+) -> Result<Reply, CommandError> {
+    let invalid = || CommandError::Usage(format!("Can't play {}", uri));
+
+    if let Ok(id) = TrackId::from_uri(uri) {
+        reauth(spotify, || {
+            spotify.start_uris_playback(
+                Some(PlayableId::from(id.clone())),
+                Some(&active_device.id),
+                None,
+                None,
+            )
+        })
+        .await
+        .map_err(|err| CommandError::failed("Could not start playing song", err))?;
+        return Ok(Reply::Message(format!("Started playing: {}", uri)));
+    }
+
+    let context = match parse_uri(uri).map_err(|_| invalid())? {
+        (Type::Album, _) => PlayContextId::from(AlbumId::from_uri(uri).map_err(|_| invalid())?),
+        (Type::Playlist, _) => {
+            PlayContextId::from(PlaylistId::from_uri(uri).map_err(|_| invalid())?)
+        }
+        (Type::Artist, _) => PlayContextId::from(ArtistId::from_uri(uri).map_err(|_| invalid())?),
+        (Type::Show, _) => PlayContextId::from(ShowId::from_uri(uri).map_err(|_| invalid())?),
+        _ => return Err(invalid()),
+    };
+
+    reauth(spotify, || {
+        spotify.start_context_playback(
+            context.as_ref(),
+            Some(&active_device.id),
+            offset.map(|track| Offset::Uri(track.to_string())),
+            None,
+        )
+    })
+    .await
+    .map_err(|err| CommandError::failed("Could not start playing", err))?;
+
+    Ok(Reply::Message(format!("Started playing: {}", uri)))
+}
+
+// how many seconds to seek, asks if it wasn't given with the command
+fn seek_seconds(seconds: Option<i64>, direction: &str) -> Result<i64, CommandError> {
+    if let Some(seconds) = seconds {
+        if !(1..=MAX_SEEK_SECONDS).contains(&seconds) {
+            return Err(CommandError::Usage(format!(
+                "Can only go {} between 1 and {} seconds",
+                direction, MAX_SEEK_SECONDS
+            )));
+        }
+        return Ok(seconds);
+    }
+
+    let seconds = vec![5, 10, 15, 20, 30, 45, 60];
+    let selection = select(
+        &format!("Choose how many seconds {} you want to go", direction),
+        &seconds,
+    )
+    .ok_or(CommandError::NeedsInput("Give the amount of seconds"))?;
+
+    Ok(seconds[selection])
+}
+
+// uses the query given with the command or asks for one, either way it ends
+// up in the profile's search history
+fn query_or_ask(
+    query: Option<String>,
+    prompt: &str,
+    profile_name: &str,
+) -> Result<String, CommandError> {
+    if let Some(query) = query {
+        remember_search(profile_name, &query);
+        return Ok(query);
+    }
+
+    if !can_prompt() {
+        return Err(CommandError::NeedsInput("Give a search query"));
+    }
+
+    let mut editor = LineEditor::open(profile_name, HistoryKind::Searches)
+        .map_err(|err| CommandError::failed("Could not start the line editor", err))?;
+    let query = editor
+        .read(&format!("{}: ", prompt))
+        .ok_or(CommandError::NeedsInput("Give a search query"))?;
+
+    Ok(query.trim().to_string())
+}
+
+///// QUEUE /////
+#[derive(Debug, Serialize)]
+struct QueueItem {
+    name: String,
+    artists: Vec<String>,
+    #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+    duration: TimeDelta,
+}
+
+async fn get_queue(spotify: &impl Authorize) -> Result<Vec<QueueItem>, CommandError> {
+    let queue = reauth(spotify, || spotify.current_user_queue())
+        .await
+        .map_err(|err| CommandError::failed("Could not get the queue", err))?;
+
+    Ok(queue
+        .queue
+        .into_iter()
+        .map(|item| match item {
+            PlayableItem::Track(track) => QueueItem {
+                name: track.name,
+                artists: track
+                    .artists
+                    .iter()
+                    .map(|artist| artist.name.clone())
+                    .collect(),
+                duration: track.duration,
+            },
+            PlayableItem::Episode(episode) => QueueItem {
+                name: episode.name,
+                artists: vec![episode.show.publisher],
+                duration: episode.duration,
+            },
+        })
+        .collect())
+}
+
+fn list_queue(queue: &[QueueItem]) -> String {
+    if queue.is_empty() {
+        return "Nothing is queued".to_string();
     }
+
+    let mut list = String::from("Up next:");
+    for (i, item) in queue.iter().enumerate() {
+        list += &format!(
+            "\n{:>3}. {} - {} ({})",
+            i + 1,
+            item.name,
+            item.artists.join(", "),
+            format_duration(item.duration)
+        );
+    }
+    list
+}
+
+// without a terminal to pick in the top result is queued
+async fn queue_song(
+    spotify: &impl Authorize,
+    query: &str,
+    active_device: &Device,
+) -> Result<Reply, CommandError> {
+    // queue spotify:track:.. doesn't need a search
+    if let Ok(id) = TrackId::from_uri(query) {
+        reauth(spotify, || {
+            spotify.add_item_to_queue(PlayableId::from(id.clone()), Some(&active_device.id))
+        })
+        .await
+        .map_err(|err| CommandError::failed("Could not add the song to the queue", err))?;
+        return Ok(Reply::Message(format!("Added to the queue: {}", query)));
+    }
+
+    let search_data = find_songs(spotify, query).await;
+    if search_data.is_empty() {
+        return Err(CommandError::NotFound("No songs found".to_string()));
+    }
+
+    let song_names: Vec<String> = search_data
+        .iter()
+        .map(|track| format!("{} - {}", track.song_name, track.artists.join(", ")))
+        .collect();
+
+    let selection = select("Select song to queue", &song_names).unwrap_or(0);
+    let selected_song = &search_data[selection];
+
+    reauth(spotify, || {
+        spotify.add_item_to_queue(
+            PlayableId::from(selected_song.id.clone()),
+            Some(&active_device.id),
+        )
+    })
+    .await
+    .map_err(|err| CommandError::failed("Could not add the song to the queue", err))?;
+
+    Ok(Reply::data(
+        format!("Added to the queue: {}", selected_song.song_name),
+        json!({ "queued": selected_song, "results": search_data }),
+    ))
+}
+
+///// STARTUP /////
+// the client a profile logs in with
+enum Client {
+    AuthCode(AuthCodeSpotify),
+    Pkce(AuthCodePkceSpotify),
+    ReadOnly(ClientCredsSpotify),
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
 
-    let client_id = get_env("RSPOTIFY_CLIENT_ID");
-    let client_secret = get_env("RSPOTIFY_CLIENT_SECRET");
+    let matches = cli::cli().get_matches();
 
-    if client_id == "" || client_secret == "" {
-        return Err("One or more of the necessary env variables were not found".into());
+    let mut profile = matches
+        .get_one::<String>("profile")
+        .cloned()
+        .unwrap_or(DEFAULT_PROFILE.to_string());
+
+    // no colors in the json
+    let output = cli::output(&matches);
+    let flags = cli::login_flags(&matches);
+    if output == Output::Json {
+        colored::control::set_override(false);
     }
 
-    // user authentication
-    let spotify = authorize_user(&client_id, &client_secret).await?;
+    let mode = cli::mode(&matches);
 
-    let devices = get_available_devices(&spotify).await;
+    // the repl and the tui are for people, json is only for single commands
+    if output == Output::Json && matches!(mode, Mode::Repl | Mode::Tui) {
+        eprint_err(
+            "--output json only works with a command, ex: terminal-spotify --output json status",
+        );
+        std::process::exit(2);
+    }
 
-    let mut active_device = Device {
-        ..Default::default()
+    let tui = match mode {
+        Mode::Repl => false,
+        Mode::Tui => true,
+        // a subcommand runs once and exits, for scripts and keybindings
+        Mode::Once(command) => {
+            let code = match command {
+                Ok(command) => run_once(&profile, flags, command, output).await,
+                Err(err) => print_result(Err(err.into()), output),
+            };
+            std::process::exit(code);
+        }
+        Mode::Daemon { http } => return daemon::serve(&profile, flags, http).await,
+        Mode::Ctl(words) => std::process::exit(daemon::ctl(&profile, words, output).await),
+        Mode::LastFmLogin => return scrobble::lastfm_login().await,
     };
 
-    // prints and sets active_device
-    print_devices(&devices, &mut active_device);
+    let mut previous_profile: Option<String> = None;
 
+    // a session ends with the name of the next profile when the user switches
     loop {
-        // force print out the > to make it appear before user_input
-        print!("{} ", "->".bold().bright_green());
-        std::io::stdout().flush().unwrap();
+        match start_session(&profile, flags, tui).await {
+            Ok(Some(next_profile)) => {
+                previous_profile = Some(profile);
+                profile = next_profile;
+            }
+            Ok(None) => break,
+            // switching failed, go back to the profile that worked
+            Err(err) => match previous_profile.take() {
+                Some(previous) => {
+                    eprint_err(&format!("Could not switch profile: {}", err));
+                    profile = previous;
+                }
+                None => return Err(err),
+            },
+        }
+    }
 
-        let input = user_input();
+    println!("Exiting..");
 
-        if input.trim().is_empty() {
-            continue;
+    Ok(())
+}
+
+async fn login(
+    profile_name: &str,
+    flags: LoginFlags,
+) -> Result<(Profile, Client), Box<dyn std::error::Error>> {
+    check_profile_name(profile_name)?;
+    let profile = match AppConfig::load()?.profile(profile_name) {
+        Some(profile) => profile,
+        None => return Err(format!("Profile {} was not found in the config", profile_name).into()),
+    };
+
+    let client_id = profile.client_id();
+
+    if profile.use_read_only(flags.read_only) {
+        let client_secret = client_secret(profile_name, &profile).await;
+
+        if client_id.is_empty() || client_secret.is_empty() {
+            return Err("One or more of the necessary env variables were not found".into());
         }
 
-        let mut currently_playing = CurrentlyPlaying {
-            is_playing: false,
-            progress: Duration::new(0, 0),
-            song_name: "".to_string(),
-            artists: vec![],
-        };
+        // no user login, only the app itself is authenticated
+        let spotify = authorize_client_creds(&client_id, &client_secret).await?;
+        return Ok((profile, Client::ReadOnly(spotify)));
+    }
 
-        if active_device.id != "" {
-            currently_playing = get_currently_playing(&spotify).await?;
+    if profile.use_pkce(flags.pkce) {
+        if client_id.is_empty() {
+            return Err("RSPOTIFY_CLIENT_ID is needed to log in".into());
         }
 
-        match input.trim() {
-            "help" => println!(
-                "Available commands:\n\n{}\nhelp -> get a list of available commands\nexit -> exit\nactivate -> select a device you want to activate\n\n{}\ns/song -> search for and play a song\nalbum -> search for and play an album\nplaylist -> search for and play a personal playlist\np -> resumes or pauses track, depending on which one is possible\nplay -> resume playback\npause -> pause playback\nrestart -> restarts track\nnext/prev -> skips to next or previous track\nforward/back -> select amount of seconds to go back or forward\nstatus -> get status of currently selected song",
-                "Always available".bold().yellow(),
-                "If a device is active:".bold().green()
-            ),
-            "activate" => activate_device(&spotify, &devices, &mut active_device).await,
-            "devices" => {
-                let devices = get_available_devices(&spotify).await;
-                print_devices(&devices, &mut active_device)
-            }
-            // search temp working here
-            "song" | "s" | "search" => {
-                print!("Search for a song: ");
-                std::io::stdout().flush().unwrap();
+        // user authentication
+        let spotify = authorize_user_pkce(&client_id, profile_name, &profile).await?;
+        return Ok((profile, Client::Pkce(spotify)));
+    }
+
+    let client_secret = client_secret(profile_name, &profile).await;
 
-                let q = user_input();
-                search_song(&spotify, q.trim(), &mut active_device).await;
+    if client_id.is_empty() || client_secret.is_empty() {
+        return Err("One or more of the necessary env variables were not found".into());
+    }
+
+    // user authentication
+    let spotify = authorize_user(&client_id, &client_secret, profile_name, &profile).await?;
+    Ok((profile, Client::AuthCode(spotify)))
+}
+
+async fn start_session(
+    profile_name: &str,
+    flags: LoginFlags,
+    tui: bool,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    match login(profile_name, flags).await? {
+        (_, Client::ReadOnly(_)) if tui => {
+            Err("The tui needs a user login, start it without --read-only".into())
+        }
+        (_, Client::ReadOnly(spotify)) => browse::run_read_only(spotify, profile_name).await,
+        (profile, Client::Pkce(spotify)) if tui => tui::run(spotify, profile_name, &profile).await,
+        (profile, Client::AuthCode(spotify)) if tui => {
+            tui::run(spotify, profile_name, &profile).await
+        }
+        (profile, Client::Pkce(spotify)) => run(spotify, profile_name, &profile).await,
+        (profile, Client::AuthCode(spotify)) => run(spotify, profile_name, &profile).await,
+    }
+}
+
+// runs a single command from the command line, returns the exit code
+async fn run_once(profile_name: &str, flags: LoginFlags, command: Command, output: Output) -> i32 {
+    let res = match login(profile_name, flags).await {
+        Ok((_, Client::ReadOnly(spotify))) => {
+            browse::execute_read_only(&spotify, profile_name, command).await
+        }
+        Ok((profile, Client::Pkce(mut spotify))) => {
+            execute_once(&mut spotify, profile_name, &profile, command, output).await
+        }
+        Ok((profile, Client::AuthCode(mut spotify))) => {
+            execute_once(&mut spotify, profile_name, &profile, command, output).await
+        }
+        Err(err) => Err(CommandError::failed("Could not log in", err)),
+    };
+
+    print_result(res, output)
+}
+
+async fn execute_once(
+    spotify: &mut impl Authorize,
+    profile_name: &str,
+    profile: &Profile,
+    command: Command,
+    output: Output,
+) -> Result<Reply, CommandError> {
+    // runs until it's killed, doesn't need a device, prints json lines with
+    // --output json too
+    if let Command::Status {
+        follow: true,
+        format,
+        json,
+    } = &command
+    {
+        ensure_scopes(spotify, command.scopes()).await?;
+        let config = AppConfig::load()
+            .map_err(|err| CommandError::failed("Could not read the config", err))?;
+        let json = *json || (output == Output::Json && format.is_none());
+        follow_status(spotify, format.as_deref(), json, &config.events).await?;
+        return Ok(Reply::Done);
+    }
+
+    let mut session = Session::start(spotify, profile_name).await?;
+
+    if let Err(err) = session.activate_default_device(spotify, profile).await {
+        eprint_err(&err.to_string());
+    }
+
+    if command == Command::Keys {
+        let config = AppConfig::load()
+            .map_err(|err| CommandError::failed("Could not read the config", err))?;
+        return hotkeys::run(spotify, &mut session, &config.keys(profile_name)).await;
+    }
+
+    execute(spotify, &mut session, command).await
+}
+
+async fn run(
+    mut spotify: impl Authorize + 'static,
+    profile_name: &str,
+    profile: &Profile,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let mut session = Session::start(&spotify, profile_name).await?;
+
+    // prints and sets active_device
+    match list_devices(&session.devices, &mut session.active_device) {
+        Ok(devices) => println!("{}", devices),
+        Err(err) => print_err(&err.to_string()),
+    }
+
+    match session.activate_default_device(&spotify, profile).await {
+        Ok(Some(message)) => println!("{}", message),
+        Ok(None) => (),
+        Err(err) => print_err(&err.to_string()),
+    }
+
+    // notifications and scrobbling, stop with the session
+    let config = AppConfig::load()?;
+    let _watcher = events::watch(&spotify, &config);
+
+    // for tab completion
+    session.playlists = get_playlists(&spotify)
+        .await
+        .into_iter()
+        .map(|playlist| playlist.name)
+        .collect();
+
+    let keys = config.keys(profile_name);
+    let aliases = config.aliases(profile_name);
+
+    let mut editor = LineEditor::open(profile_name, HistoryKind::Commands)?;
+    let mut prompt = format!("{} ", "->".bold().bright_green());
+    if profile_name != DEFAULT_PROFILE {
+        prompt = format!("{} {}", profile_name.bold().bright_blue(), prompt);
+    }
+
+    'session: loop {
+        editor.complete_with(session.completions());
+
+        // ctrl-d ends the session
+        let input = match editor.read(&prompt) {
+            Some(input) => input,
+            None => break,
+        };
+        // an alias or `a ; b` is more than one command
+        let lines = match alias::expand(&input, &aliases) {
+            Ok(lines) => lines,
+            Err(err) => {
+                print_err(&err.to_string());
+                continue;
             }
-            "album" | "a" => {
-                print!("Search for a song: ");
-                std::io::stdout().flush().unwrap();
+        };
 
-                let q = user_input();
-                search_album(&spotify, q.trim(), &mut active_device).await 
+        for line in lines {
+            // the rest is skipped when one of them fails
+            let command = match parse_input(&line) {
+                Some(command) => command,
+                None => break,
+            };
+
+            let reply = match command {
+                Command::Keys => hotkeys::run(&mut spotify, &mut session, &keys).await,
+                command => execute(&mut spotify, &mut session, command).await,
+            };
+            if let Ok(reply) = &reply {
+                session.remember_artists(reply);
             }
-            "playlist" | "playlists" => select_playlist(&spotify, &mut active_device).await,
-            "p" => {
-                if active_device.id == "" {
-                    print_err("Can't resume/pause playback because there is no active device");
-                    continue;
+
+            match reply {
+                Ok(Reply::Done) => (),
+                Ok(Reply::Message(message)) | Ok(Reply::Data { text: message, .. }) => {
+                    println!("{}", message)
                 }
-                if currently_playing.is_playing {
-                    match spotify.pause_playback(Some(&active_device.id)).await {
-                        Ok(_) => println!("Paused playback"),
-                        Err(err) => printf_err("Could not pause playback", err),
+                Ok(Reply::Exit(message)) => {
+                    if let Some(message) = message {
+                        println!("{}", message);
                     }
-                    continue
-                }
-                match spotify
-                    .resume_playback(Some(&active_device.id), Duration::zero().into())
-                    .await
-                {
-                    Ok(_) => println!("Resumed playback"),
-                    Err(err) => printf_err("Could not resume playback", err),
-                }
-            }
-            "play" => {
-                // TODO: find a way to better check for active_device_id where it is needed
-                if active_device.id == "" {
-                    print_err("Can't resume playback because there is no active device");
-                    continue;
-                }
-                if currently_playing.is_playing {
-                    println!("Already playing");
-                    continue;
+                    break 'session;
                 }
-                match spotify
-                    .resume_playback(Some(&active_device.id), Duration::zero().into())
-                    .await
-                {
-                    Ok(_) => println!("Resumed playback"),
-                    Err(err) => printf_err("Could not resume playback", err),
+                Ok(Reply::SwitchProfile(next_profile)) => return Ok(Some(next_profile)),
+                Err(err) => {
+                    print_err(&err.to_string());
+                    break;
                 }
             }
-            "pause" => {
-                if active_device.id == "" {
-                    print_err("Can't pause playback because there is no active device");
-                    continue;
-                }
-                if !currently_playing.is_playing {
-                    println!("Already paused");
-                    continue;
-                }
-                match spotify.pause_playback(Some(&active_device.id)).await {
-                    Ok(_) => println!("Paused playback"),
-                    Err(err) => printf_err("Could not pause playback", err),
-                }
-            }
-            "restart" | "r" => {
-                // use seek to position to set position to 0 ms
-                match spotify.seek_track(Duration::zero().into(), Some(&active_device.id)).await {
-                    Ok(_) => println!("Restarted track"),
-                    Err(err) => printf_err("Could not restart track", err),
-                }
-            }
-            "next" => {
-                match spotify.next_track(Some(&active_device.id)).await {
-                    Ok(_) => println!("Skipped to next track"),
-                    Err(err) => printf_err("Could not skip to next track", err),
-                }
-            }
-            "prev" | "previous" => {
-                match spotify.previous_track(Some(&active_device.id)).await {
-                    Ok(_) => println!("Skipped to previous track"),
-                    Err(err) => printf_err("Could not skip to previous track", err),
-                }
-            }
-            "fwd" | "forward" => {
-                let seconds = vec![5, 10, 15, 20, 30, 45, 60];
-                let selection = Select::new()
-                    .with_prompt("Choose how many seconds forward you want to go")
-                    .items(&seconds)
-                    .interact()
-                    .unwrap();
-                let seconds_forward = currently_playing.progress.unwrap_or(Duration::zero()).num_seconds() + seconds[selection];
-                match spotify.seek_track(Duration::new(seconds_forward as i64, 0).unwrap(), Some(&active_device.id)).await {
-                    Ok(_) => println!("Skipped forward {} seconds", seconds[selection]),
-                    Err(err) => printf_err("Could not skip forward", err)
-                }
-            }
-            "back" => {
-                let seconds = vec![5, 10, 15, 20, 30, 45, 60];
-                let selection = Select::new()
-                    .with_prompt("Choose how many seconds back you want to go")
-                    .items(&seconds)
-                    .interact()
-                    .unwrap();
-                let mut seconds_back = currently_playing.progress.unwrap_or(Duration::zero()).num_seconds() - seconds[selection];
-                if seconds_back < 0 {
-                    seconds_back = 0
-                }
-                match spotify.seek_track(Duration::new(seconds_back, 0).unwrap(), Some(&active_device.id)).await {
-                    Ok(_) => println!("Skipped back {} seconds", seconds[selection]),
-                    Err(err) => printf_err("Could not skip back", err)
-                }
-            }
-            "status" => {
-                if !currently_playing.is_playing {
-                    println!("You are not listening to anything at the moment");
-                    continue;
-                }
-
-                if currently_playing.is_playing {
-                    println!(
-                        "You are listening to {} by {}. Progress: {}",
-                        currently_playing.song_name,
-                        currently_playing.artists.join(", "),
-                        // TODO: make this display with this format minutes:seconds, ex: 16:47
-                        match currently_playing.progress {
-                            Some(time) => time.num_seconds(),
-                            None => 0,
-                        }
-                    );
-                }
-            }
-            "exit" => {
-                let exit_condition = you_can_not_leave();
-                if exit_condition {
-                    break
-                }
-            },
-            _ => printf_err("Command not found", input),
         }
     }
 
-    println!("Exiting..");
-
-    Ok(())
+    Ok(None)
 }
@@ -0,0 +1,386 @@
+use chrono::TimeDelta;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use terminal_spotify::eprint_err;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc, oneshot,
+};
+use zbus::{
+    fdo, interface,
+    object_server::SignalContext,
+    zvariant::{ObjectPath, OwnedValue, Value},
+};
+
+use crate::{
+    auth::Authorize,
+    daemon::{run_request, SharedSession},
+    events::{get_player_state, Event, PlayerState},
+    output::Response,
+};
+
+///// MPRIS /////
+// shows the daemon as a media player on the session bus, so media keys,
+// playerctl and status bars can control it. the state comes from the event
+// poller and the methods run the same commands as the socket
+//
+// try it without a desktop with:
+// dbus-run-session -- sh -c 'terminal-spotify daemon & sleep 5; playerctl -p terminal_spotify status'
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.terminal_spotify";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+// the player state and when it was last updated, to move the position along
+type Snapshot = Arc<Mutex<(Instant, PlayerState)>>;
+
+// a command and where to send the answer
+type Commands = mpsc::Sender<(String, oneshot::Sender<Response>)>;
+
+pub async fn serve<S: Authorize + Send + 'static>(
+    state: SharedSession<S>,
+    events: broadcast::Sender<Event>,
+) {
+    if let Err(err) = run(state, events).await {
+        eprint_err(&format!("Could not show up on the session bus: {}", err));
+    }
+}
+
+async fn run<S: Authorize + Send + 'static>(
+    state: SharedSession<S>,
+    events: broadcast::Sender<Event>,
+) -> zbus::Result<()> {
+    // subscribe first so nothing is missed while connecting
+    let mut events = events.subscribe();
+
+    let player_state = {
+        let state = state.lock().await;
+        get_player_state(&state.0).await.unwrap_or_default()
+    };
+    let snapshot: Snapshot = Arc::new(Mutex::new((Instant::now(), player_state)));
+
+    let (commands, mut received) = mpsc::channel::<(String, oneshot::Sender<Response>)>(8);
+    tokio::spawn(async move {
+        while let Some((command, reply)) = received.recv().await {
+            let (response, _) = run_request(&state, &command).await;
+            let _ = reply.send(response);
+        }
+    });
+
+    let connection = zbus::connection::Builder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, Root)?
+        .serve_at(
+            OBJECT_PATH,
+            Player {
+                commands,
+                snapshot: snapshot.clone(),
+            },
+        )?
+        .build()
+        .await?;
+
+    println!("Showing up on the session bus as {}", BUS_NAME);
+
+    let player = connection
+        .object_server()
+        .interface::<_, Player>(OBJECT_PATH)
+        .await?;
+
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return Ok(()),
+        };
+
+        {
+            let mut snapshot = snapshot.lock().unwrap();
+            snapshot.1.apply(&event);
+            snapshot.0 = Instant::now();
+        }
+
+        let ctxt = player.signal_context();
+        let iface = player.get().await;
+        match event {
+            Event::TrackChanged { .. } => {
+                iface.metadata_changed(ctxt).await?;
+                iface.playback_status_changed(ctxt).await?;
+            }
+            Event::Paused { .. } | Event::Resumed { .. } => {
+                iface.playback_status_changed(ctxt).await?;
+            }
+            Event::Seeked { progress_ms } => {
+                Player::seeked(ctxt, progress_ms * 1000).await?;
+            }
+            Event::VolumeChanged { .. } => iface.volume_changed(ctxt).await?,
+            Event::DeviceChanged { .. } => (),
+        }
+    }
+}
+
+///// org.mpris.MediaPlayer2 /////
+struct Root;
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    // there is no window to raise and the daemon is stopped with ctl logout
+    // or a signal
+    fn raise(&self) {}
+
+    fn quit(&self) {}
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> String {
+        String::from("terminal-spotify")
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+///// org.mpris.MediaPlayer2.Player /////
+struct Player {
+    commands: Commands,
+    snapshot: Snapshot,
+}
+
+impl Player {
+    async fn run(&self, command: String) -> fdo::Result<()> {
+        let (reply, answer) = oneshot::channel();
+        self.commands
+            .send((command, reply))
+            .await
+            .map_err(|_| fdo::Error::Failed("The daemon is stopping".to_string()))?;
+
+        match answer.await {
+            Ok(Response { ok: true, .. }) => Ok(()),
+            Ok(Response { error, .. }) => Err(fdo::Error::Failed(error.unwrap_or_default())),
+            Err(_) => Err(fdo::Error::Failed("The daemon is stopping".to_string())),
+        }
+    }
+
+    fn state(&self) -> PlayerState {
+        self.snapshot.lock().unwrap().1.clone()
+    }
+
+    // the position now, moved along since the last event if playing
+    fn position_now(&self) -> TimeDelta {
+        let (updated_at, state) = &*self.snapshot.lock().unwrap();
+        if !state.is_playing {
+            return state.progress;
+        }
+        (state.progress + TimeDelta::from_std(updated_at.elapsed()).unwrap_or_default())
+            .min(state.duration.max(state.progress))
+    }
+
+    async fn seek_to(&self, position: TimeDelta) -> fdo::Result<()> {
+        self.run(format!("seek {}", position.num_seconds().max(0)))
+            .await
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    async fn next(&self) -> fdo::Result<()> {
+        self.run("next".to_string()).await
+    }
+
+    async fn previous(&self) -> fdo::Result<()> {
+        self.run("prev".to_string()).await
+    }
+
+    async fn pause(&self) -> fdo::Result<()> {
+        self.run("pause".to_string()).await
+    }
+
+    async fn play_pause(&self) -> fdo::Result<()> {
+        self.run("p".to_string()).await
+    }
+
+    // spotify can't stop, pausing is the closest
+    async fn stop(&self) -> fdo::Result<()> {
+        self.run("pause".to_string()).await
+    }
+
+    async fn play(&self) -> fdo::Result<()> {
+        self.run("play".to_string()).await
+    }
+
+    // offset in microseconds, seeking past the end goes to the next track
+    async fn seek(&self, offset: i64) -> fdo::Result<()> {
+        let position = self.position_now() + TimeDelta::microseconds(offset);
+        let duration = self.state().duration;
+        if duration > TimeDelta::zero() && position > duration {
+            return self.next().await;
+        }
+        self.seek_to(position).await
+    }
+
+    // ignored if the track changed since the client looked
+    async fn set_position(&self, track_id: ObjectPath<'_>, position: i64) -> fdo::Result<()> {
+        let state = self.state();
+        let position = TimeDelta::microseconds(position);
+        if track_id.as_str() != track_path(&state).as_str()
+            || position < TimeDelta::zero()
+            || position > state.duration
+        {
+            return Ok(());
+        }
+        self.seek_to(position).await
+    }
+
+    fn open_uri(&self, _uri: String) -> fdo::Result<()> {
+        Err(fdo::Error::NotSupported(
+            "Opening uris is not supported".to_string(),
+        ))
+    }
+
+    #[zbus(signal)]
+    async fn seeked(ctxt: &SignalContext<'_>, position: i64) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn playback_status(&self) -> String {
+        let state = self.state();
+        match state.track_id {
+            None if state.title.is_empty() => "Stopped",
+            _ if state.is_playing => "Playing",
+            _ => "Paused",
+        }
+        .to_string()
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, OwnedValue> {
+        metadata(&self.state())
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        self.state().volume.unwrap_or(0) as f64 / 100.0
+    }
+
+    #[zbus(property)]
+    async fn set_volume(&mut self, volume: f64) {
+        let percent = (volume.clamp(0.0, 1.0) * 100.0).round() as u8;
+        if let Err(err) = self.run(format!("volume {}", percent)).await {
+            eprint_err(&err.to_string());
+        }
+    }
+
+    // clients ask for this when they need it, it doesn't send changes
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        self.position_now().num_microseconds().unwrap_or(0)
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        true
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+///// METADATA /////
+// ex: spotify:track:6rqhFgbbKwnb9MLmUQDhG6 -> /org/terminal_spotify/track/6rqhFgbbKwnb9MLmUQDhG6
+fn track_path(state: &PlayerState) -> ObjectPath<'static> {
+    state
+        .track_id
+        .as_ref()
+        .and_then(|uri| {
+            let parts: Vec<&str> = uri.split(':').skip(1).collect();
+            ObjectPath::try_from(format!("/org/terminal_spotify/{}", parts.join("/"))).ok()
+        })
+        .unwrap_or_else(|| ObjectPath::from_static_str_unchecked(NO_TRACK))
+}
+
+fn metadata(state: &PlayerState) -> HashMap<String, OwnedValue> {
+    let mut metadata = HashMap::new();
+    let mut insert = |key: &str, value: Value| {
+        if let Ok(value) = OwnedValue::try_from(value) {
+            metadata.insert(key.to_string(), value);
+        }
+    };
+
+    insert("mpris:trackid", Value::from(track_path(state)));
+    if state.track_id.is_none() && state.title.is_empty() {
+        return metadata;
+    }
+
+    insert(
+        "mpris:length",
+        Value::from(state.duration.num_microseconds().unwrap_or(0)),
+    );
+    insert("xesam:title", Value::from(state.title.clone()));
+    insert("xesam:artist", Value::from(state.artists.clone()));
+    insert("xesam:album", Value::from(state.album.clone()));
+    if let Some(uri) = &state.track_id {
+        insert("xesam:url", Value::from(uri.clone()));
+    }
+
+    metadata
+}
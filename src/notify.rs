@@ -0,0 +1,188 @@
+use std::{collections::HashMap, env, path::PathBuf, time::Duration};
+use terminal_spotify::eprint_err;
+use tokio::{
+    fs,
+    process::Command,
+    sync::broadcast::{self, error::RecvError},
+};
+use zbus::{proxy, zvariant::Value, Connection};
+
+use crate::{
+    config::{NotificationsConfig, Urgency},
+    events::{Event, PlayerState},
+};
+
+///// NOTIFICATIONS /////
+// a desktop notification with the title, artists, album and cover every time
+// the track changes, built on the event poller
+
+#[proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications",
+    gen_blocking = false
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+}
+
+// `terminal` is true when running in the repl, the daemon has no terminal to
+// be focused
+pub async fn notify_tracks(
+    mut events: broadcast::Receiver<Event>,
+    config: NotificationsConfig,
+    terminal: bool,
+) {
+    let notifications = match connect().await {
+        Ok(notifications) => notifications,
+        Err(err) => {
+            eprint_err(&format!(
+                "Could not connect to the notification daemon: {}",
+                err
+            ));
+            return;
+        }
+    };
+
+    // every notification replaces the one for the track before
+    let mut last_id = 0;
+
+    loop {
+        let track = match events.recv().await {
+            Ok(Event::TrackChanged { track }) => track,
+            Ok(_) | Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return,
+        };
+
+        // nothing is playing anymore
+        if track.title.is_empty() {
+            continue;
+        }
+
+        if terminal && config.suppress_when_focused && terminal_focused().await {
+            continue;
+        }
+
+        let icon = match &track.cover_url {
+            Some(url) if config.cover_art => cover_path(url).await,
+            _ => None,
+        };
+
+        match notify(&notifications, last_id, &track, icon, config.urgency).await {
+            Ok(id) => last_id = id,
+            Err(err) => eprint_err(&format!("Could not show a notification: {}", err)),
+        }
+    }
+}
+
+async fn connect() -> zbus::Result<NotificationsProxy<'static>> {
+    let connection = Connection::session().await?;
+    NotificationsProxy::new(&connection).await
+}
+
+async fn notify(
+    notifications: &NotificationsProxy<'_>,
+    replaces_id: u32,
+    track: &PlayerState,
+    icon: Option<PathBuf>,
+    urgency: Urgency,
+) -> zbus::Result<u32> {
+    let icon = icon
+        .map(|path| path.display().to_string())
+        .unwrap_or_default();
+
+    let mut body = track.artists.join(", ");
+    if !track.album.is_empty() {
+        body = format!("{}\n{}", body, track.album);
+    }
+
+    let mut hints = HashMap::new();
+    hints.insert("urgency", Value::U8(urgency as u8));
+    hints.insert("category", Value::from("x-terminal-spotify.track"));
+
+    notifications
+        .notify(
+            "terminal-spotify",
+            replaces_id,
+            &icon,
+            &track.title,
+            &body,
+            &[],
+            hints,
+            -1,
+        )
+        .await
+}
+
+// how long downloading a cover may take
+const COVER_TIMEOUT: Duration = Duration::from_secs(10);
+
+// the cover is downloaded once to ~/.cache/terminal-spotify/covers, the
+// notification daemon needs a file
+async fn cover_path(url: &str) -> Option<PathBuf> {
+    let name = url.rsplit('/').next().filter(|name| !name.is_empty())?;
+    let dir = dirs::cache_dir()?.join("terminal-spotify").join("covers");
+    let path = dir.join(format!("{}.jpg", name));
+
+    if path.exists() {
+        return Some(path);
+    }
+
+    // a slow download only means the notification comes without a cover
+    let client = reqwest::Client::builder()
+        .timeout(COVER_TIMEOUT)
+        .build()
+        .ok()?;
+    let bytes = client
+        .get(url)
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?
+        .bytes()
+        .await
+        .ok()?;
+
+    // written next to it first so a cut off download is never taken for a cover
+    fs::create_dir_all(&dir).await.ok()?;
+    let partial = dir.join(format!("{}.jpg.part", name));
+    if fs::write(&partial, bytes).await.is_err() {
+        let _ = fs::remove_file(&partial).await;
+        return None;
+    }
+    fs::rename(&partial, &path).await.ok()?;
+
+    Some(path)
+}
+
+// only known for x11 terminals that set $WINDOWID (xterm, alacritty, kitty..)
+// and with xdotool installed, anything else counts as not focused
+async fn terminal_focused() -> bool {
+    let window = match env::var("WINDOWID") {
+        Ok(window) => window,
+        Err(_) => return false,
+    };
+
+    match Command::new("xdotool")
+        .arg("getactivewindow")
+        .output()
+        .await
+    {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).trim() == window.trim()
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use terminal_spotify::eprint_err;
+
+use crate::command::{CommandError, Reply};
+
+///// OUTPUT /////
+// --output json prints one json object per command instead of text, the same
+// one the daemon and the rest api answer with
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Output {
+    #[default]
+    Text,
+    Json,
+}
+
+// ex:
+// {"ok":true,"message":"Volume set to 40%","code":0}
+// {"ok":true,"message":"Available devices:..","data":[{"id":"..","name":"Kitchen","is_active":true}],"code":0}
+// {"ok":false,"error":"No songs found","code":4}
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    // devices, search results, what is playing.. for commands that have more
+    // than a message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub code: i32,
+}
+
+impl Response {
+    pub fn ok(message: Option<String>) -> Response {
+        Response {
+            ok: true,
+            message,
+            data: None,
+            error: None,
+            code: 0,
+        }
+    }
+
+    pub fn err(err: CommandError) -> Response {
+        Response {
+            ok: false,
+            message: None,
+            data: None,
+            error: Some(err.to_string()),
+            code: err.exit_code(),
+        }
+    }
+
+    pub fn from_result(res: Result<Reply, CommandError>) -> Response {
+        match res {
+            Ok(Reply::Done) | Ok(Reply::SwitchProfile(_)) => Response::ok(None),
+            Ok(Reply::Message(message)) => Response::ok(Some(message)),
+            Ok(Reply::Data { text, data }) => Response {
+                data: Some(data),
+                ..Response::ok(Some(text))
+            },
+            Ok(Reply::Exit(message)) => Response::ok(message),
+            Err(err) => Response::err(err),
+        }
+    }
+}
+
+// prints what a command answered, returns the exit code
+pub fn print_response(response: &Response, output: Output) -> i32 {
+    match output {
+        Output::Json => println!("{}", serde_json::to_string(response).unwrap()),
+        Output::Text => {
+            if let Some(message) = &response.message {
+                println!("{}", message);
+            }
+            if let Some(error) = &response.error {
+                eprint_err(error);
+            }
+        }
+    }
+    response.code
+}
+
+pub fn print_result(res: Result<Reply, CommandError>, output: Output) -> i32 {
+    print_response(&Response::from_result(res), output)
+}
@@ -0,0 +1,13 @@
+use std::collections::HashSet;
+
+///// SCOPES /////
+// scopes asked for on the first login, anything else is added when a command
+// that needs it is used, see the scopes in command::COMMANDS
+pub const BASE_SCOPES: &[&str] = &[
+    "user-read-playback-state",   // devices and status about player
+    "user-modify-playback-state", // interact with player
+];
+
+pub fn to_scope_set(scopes: &[&str]) -> HashSet<String> {
+    scopes.iter().map(|scope| scope.to_string()).collect()
+}
@@ -0,0 +1,527 @@
+use chrono::Utc;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+use terminal_spotify::{eprint_err, user_input};
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    time::sleep,
+};
+
+use crate::{
+    config::{config_dir, AppConfig, LastFmConfig, ListenBrainzConfig, ScrobbleConfig},
+    events::{Event, PlayerState},
+};
+
+///// SCROBBLING /////
+// sends "now playing" when a track starts and scrobbles it once it has played
+// for half its length or 4 minutes, like last.fm wants. scrobbles wait in
+// config_dir/scrobble_queue.json until the services can be reached
+
+const QUEUE_FILE: &str = "scrobble_queue.json";
+// held by the one process that scrobbles, the repl and the daemon would send
+// every track twice and overwrite each other's queue otherwise
+const LOCK_FILE: &str = "scrobble.lock";
+
+// tracks shorter than this are never scrobbled
+const MIN_DURATION_SECS: i64 = 30;
+const MAX_WAIT: Duration = Duration::from_secs(4 * 60);
+
+// how often scrobbles that didn't go through are sent again
+const RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+// both services take at most 50 at once
+const BATCH_SIZE: usize = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scrobble {
+    pub artist: String,
+    pub title: String,
+    pub album: String,
+    pub duration_secs: i64,
+    // unix time the track started
+    pub timestamp: i64,
+    pub spotify_uri: Option<String>,
+}
+
+enum Service {
+    LastFm(LastFmConfig),
+    ListenBrainz(ListenBrainzConfig),
+}
+
+impl Service {
+    fn name(&self) -> &'static str {
+        match self {
+            Service::LastFm(_) => "lastfm",
+            Service::ListenBrainz(_) => "listenbrainz",
+        }
+    }
+}
+
+fn services(config: ScrobbleConfig) -> Vec<Service> {
+    let mut services = Vec::new();
+
+    match config.lastfm {
+        Some(lastfm) if lastfm.session_key.is_some() => services.push(Service::LastFm(lastfm)),
+        Some(_) => eprint_err(
+            "Not scrobbling to last.fm, log in with `terminal-spotify lastfm-login` first",
+        ),
+        None => (),
+    }
+
+    if let Some(listenbrainz) = config.listenbrainz {
+        services.push(Service::ListenBrainz(listenbrainz));
+    }
+
+    services
+}
+
+// why a service didn't take what was sent
+#[derive(Debug)]
+enum SubmitError {
+    // offline or the service is down, try again later
+    Retry(String),
+    // it will never be taken
+    Rejected(String),
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubmitError::Retry(text) | SubmitError::Rejected(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+impl std::error::Error for SubmitError {}
+
+fn retry(err: reqwest::Error) -> SubmitError {
+    SubmitError::Retry(err.to_string())
+}
+
+///// LISTEN /////
+// the track being listened to and how long it has played
+struct Listen {
+    scrobble: Scrobble,
+    // how long it has to play before it's scrobbled
+    needed: Duration,
+    listened: Duration,
+    playing_since: Option<Instant>,
+    now_playing_sent: bool,
+    scrobbled: bool,
+}
+
+impl Listen {
+    fn start(track: &PlayerState) -> Option<Listen> {
+        if track.title.is_empty() || track.duration.num_seconds() < MIN_DURATION_SECS {
+            return None;
+        }
+        // podcast episodes have no artist and both services want one
+        let is_episode = track
+            .track_id
+            .as_ref()
+            .is_some_and(|uri| uri.starts_with("spotify:episode:"));
+        if is_episode || track.artists.is_empty() {
+            return None;
+        }
+
+        let duration = track.duration.to_std().unwrap_or_default();
+        Some(Listen {
+            scrobble: Scrobble {
+                artist: track.artists.first().cloned().unwrap_or_default(),
+                title: track.title.clone(),
+                album: track.album.clone(),
+                duration_secs: track.duration.num_seconds(),
+                timestamp: Utc::now().timestamp() - track.progress.num_seconds(),
+                spotify_uri: track.track_id.clone(),
+            },
+            needed: (duration / 2).min(MAX_WAIT),
+            listened: Duration::ZERO,
+            playing_since: track.is_playing.then(Instant::now),
+            now_playing_sent: false,
+            scrobbled: false,
+        })
+    }
+
+    fn pause(&mut self) {
+        if let Some(since) = self.playing_since.take() {
+            self.listened += since.elapsed();
+        }
+    }
+
+    fn resume(&mut self) {
+        if self.playing_since.is_none() {
+            self.playing_since = Some(Instant::now());
+        }
+    }
+
+    // how long until it should be scrobbled, None while paused or when it
+    // already is
+    fn remaining(&self) -> Option<Duration> {
+        if self.scrobbled {
+            return None;
+        }
+        let since = self.playing_since?;
+        Some(self.needed.saturating_sub(self.listened + since.elapsed()))
+    }
+}
+
+///// SCROBBLER /////
+pub async fn scrobble_tracks(mut events: broadcast::Receiver<Event>, config: ScrobbleConfig) {
+    let services = services(config);
+    if services.is_empty() {
+        return;
+    }
+
+    let client = reqwest::Client::new();
+    let mut lock: Option<fs::File> = None;
+    let mut queue = Queue::default();
+    let mut last_flush = Instant::now();
+
+    let mut listen: Option<Listen> = None;
+
+    loop {
+        // another process scrobbles until it exits, then this one takes over
+        // with whatever it left in the queue
+        if lock.is_none() {
+            lock = take_lock();
+            if lock.is_some() {
+                queue = Queue::load();
+                flush(&client, &services, &mut queue).await;
+                last_flush = Instant::now();
+            }
+        }
+
+        let wait = listen
+            .as_ref()
+            .and_then(Listen::remaining)
+            .unwrap_or(RETRY_INTERVAL)
+            .min(RETRY_INTERVAL);
+
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(Event::TrackChanged { track }) => listen = Listen::start(&track),
+                Ok(Event::Paused { .. }) => listen.iter_mut().for_each(Listen::pause),
+                Ok(Event::Resumed { .. }) => listen.iter_mut().for_each(Listen::resume),
+                Ok(_) | Err(RecvError::Lagged(_)) => (),
+                Err(RecvError::Closed) => return,
+            },
+            _ = sleep(wait) => (),
+        }
+
+        if lock.is_none() {
+            continue;
+        }
+
+        if let Some(listen) = listen.as_mut() {
+            if listen.playing_since.is_some() && !listen.now_playing_sent {
+                listen.now_playing_sent = true;
+                for service in &services {
+                    // not worth keeping, it's old news by the time it could be sent again
+                    let _ = now_playing(&client, service, &listen.scrobble).await;
+                }
+            }
+
+            if listen.remaining() == Some(Duration::ZERO) {
+                listen.scrobbled = true;
+                for service in &services {
+                    queue
+                        .0
+                        .entry(service.name().to_string())
+                        .or_default()
+                        .push(listen.scrobble.clone());
+                }
+                flush(&client, &services, &mut queue).await;
+                last_flush = Instant::now();
+            }
+        }
+
+        if !queue.is_empty() && last_flush.elapsed() >= RETRY_INTERVAL {
+            flush(&client, &services, &mut queue).await;
+            last_flush = Instant::now();
+        }
+    }
+}
+
+// None while another process holds it
+fn take_lock() -> Option<fs::File> {
+    fs::create_dir_all(config_dir()).ok()?;
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(config_dir().join(LOCK_FILE))
+        .ok()?;
+    file.try_lock().ok()?;
+    Some(file)
+}
+
+///// QUEUE /////
+// the scrobbles every service hasn't taken yet, by service name
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Queue(BTreeMap<String, Vec<Scrobble>>);
+
+impl Queue {
+    fn path() -> PathBuf {
+        config_dir().join(QUEUE_FILE)
+    }
+
+    fn load() -> Queue {
+        fs::read_to_string(Queue::path())
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    // written to a temporary file first so a crash halfway doesn't lose the queue
+    fn save(&self) {
+        let path = Queue::path();
+        let partial = path.with_extension("json.tmp");
+        let res = fs::create_dir_all(config_dir())
+            .and_then(|_| fs::write(&partial, serde_json::to_string(self).unwrap()))
+            .and_then(|_| fs::rename(&partial, &path));
+        if let Err(err) = res {
+            eprint_err(&format!("Could not save the scrobble queue: {}", err));
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.values().all(Vec::is_empty)
+    }
+}
+
+// sends what is queued, oldest first, and keeps what didn't go through
+async fn flush(client: &reqwest::Client, services: &[Service], queue: &mut Queue) {
+    for service in services {
+        let pending = queue.0.entry(service.name().to_string()).or_default();
+
+        while !pending.is_empty() {
+            let batch = pending.len().min(BATCH_SIZE);
+            match submit(client, service, &pending[..batch]).await {
+                Ok(()) => (),
+                Err(SubmitError::Rejected(err)) => eprint_err(&format!(
+                    "{} did not take {} scrobbles: {}",
+                    service.name(),
+                    batch,
+                    err
+                )),
+                Err(SubmitError::Retry(_)) => break,
+            }
+            pending.drain(..batch);
+        }
+    }
+
+    queue.save();
+}
+
+async fn now_playing(
+    client: &reqwest::Client,
+    service: &Service,
+    scrobble: &Scrobble,
+) -> Result<(), SubmitError> {
+    match service {
+        Service::LastFm(config) => {
+            let mut params = BTreeMap::new();
+            params.insert("method".to_string(), "track.updateNowPlaying".to_string());
+            params.insert("artist".to_string(), scrobble.artist.clone());
+            params.insert("track".to_string(), scrobble.title.clone());
+            params.insert("album".to_string(), scrobble.album.clone());
+            params.insert("duration".to_string(), scrobble.duration_secs.to_string());
+            lastfm_call(client, config, params).await.map(|_| ())
+        }
+        Service::ListenBrainz(config) => {
+            listenbrainz_submit(client, config, "playing_now", vec![listen(scrobble, false)]).await
+        }
+    }
+}
+
+async fn submit(
+    client: &reqwest::Client,
+    service: &Service,
+    scrobbles: &[Scrobble],
+) -> Result<(), SubmitError> {
+    match service {
+        Service::LastFm(config) => {
+            let mut params = BTreeMap::new();
+            params.insert("method".to_string(), "track.scrobble".to_string());
+            for (i, scrobble) in scrobbles.iter().enumerate() {
+                params.insert(format!("artist[{}]", i), scrobble.artist.clone());
+                params.insert(format!("track[{}]", i), scrobble.title.clone());
+                params.insert(format!("album[{}]", i), scrobble.album.clone());
+                params.insert(format!("timestamp[{}]", i), scrobble.timestamp.to_string());
+                params.insert(
+                    format!("duration[{}]", i),
+                    scrobble.duration_secs.to_string(),
+                );
+            }
+            lastfm_call(client, config, params).await.map(|_| ())
+        }
+        Service::ListenBrainz(config) => {
+            let listen_type = if scrobbles.len() == 1 {
+                "single"
+            } else {
+                "import"
+            };
+            let payload = scrobbles
+                .iter()
+                .map(|scrobble| listen(scrobble, true))
+                .collect();
+            listenbrainz_submit(client, config, listen_type, payload).await
+        }
+    }
+}
+
+///// LAST.FM /////
+// every call is signed with an md5 of the sorted parameters and the secret
+async fn lastfm_call(
+    client: &reqwest::Client,
+    config: &LastFmConfig,
+    mut params: BTreeMap<String, String>,
+) -> Result<Value, SubmitError> {
+    params.insert("api_key".to_string(), config.api_key.clone());
+    if let Some(session_key) = &config.session_key {
+        params.insert("sk".to_string(), session_key.clone());
+    }
+
+    let mut signature = String::new();
+    for (key, value) in &params {
+        signature.push_str(key);
+        signature.push_str(value);
+    }
+    signature.push_str(&config.api_secret);
+    params.insert(
+        "api_sig".to_string(),
+        format!("{:x}", md5::compute(signature)),
+    );
+    // not part of the signature
+    params.insert("format".to_string(), "json".to_string());
+
+    let res = client
+        .post(&config.url)
+        .form(&params)
+        .send()
+        .await
+        .map_err(retry)?;
+    let status = res.status();
+    let body: Value = res.json().await.map_err(retry)?;
+
+    if let Some(code) = body.get("error").and_then(Value::as_i64) {
+        let message = body["message"].as_str().unwrap_or_default().to_string();
+        // 6 is invalid parameters, anything else (down, rate limited, bad
+        // session key) can work later
+        return Err(match code {
+            6 => SubmitError::Rejected(message),
+            _ => SubmitError::Retry(message),
+        });
+    }
+    if !status.is_success() {
+        return Err(SubmitError::Retry(status.to_string()));
+    }
+
+    Ok(body)
+}
+
+// gets a session key for scrobbling, it doesn't expire
+pub async fn lastfm_login() -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = AppConfig::load()?
+        .scrobble
+        .lastfm
+        .ok_or("Add api_key and api_secret under [scrobble.lastfm] in config.toml first")?;
+    config.session_key = None;
+    let client = reqwest::Client::new();
+
+    let mut params = BTreeMap::new();
+    params.insert("method".to_string(), "auth.getToken".to_string());
+    let res = lastfm_call(&client, &config, params).await?;
+    let token = res["token"]
+        .as_str()
+        .ok_or("last.fm did not send a token")?;
+
+    let url = format!(
+        "{}?api_key={}&token={}",
+        config.auth_url, config.api_key, token
+    );
+    println!("Allow terminal-spotify to scrobble at {}", url);
+    let _ = webbrowser::open(&url);
+    println!("Press enter when done");
+    user_input();
+
+    let mut params = BTreeMap::new();
+    params.insert("method".to_string(), "auth.getSession".to_string());
+    params.insert("token".to_string(), token.to_string());
+    let res = lastfm_call(&client, &config, params).await?;
+    let session_key = res["session"]["key"]
+        .as_str()
+        .ok_or("last.fm did not send a session key")?;
+
+    println!(
+        "Add this under [scrobble.lastfm] in config.toml:\nsession_key = \"{}\"",
+        session_key
+    );
+    Ok(())
+}
+
+///// LISTENBRAINZ /////
+async fn listenbrainz_submit(
+    client: &reqwest::Client,
+    config: &ListenBrainzConfig,
+    listen_type: &str,
+    payload: Vec<Value>,
+) -> Result<(), SubmitError> {
+    let res = client
+        .post(format!(
+            "{}/1/submit-listens",
+            config.url.trim_end_matches('/')
+        ))
+        .header("Authorization", format!("Token {}", config.token))
+        .json(&json!({ "listen_type": listen_type, "payload": payload }))
+        .send()
+        .await
+        .map_err(retry)?;
+
+    let status = res.status();
+    if status.is_success() {
+        return Ok(());
+    }
+
+    let text = res.text().await.unwrap_or_else(|_| status.to_string());
+    match status {
+        StatusCode::BAD_REQUEST => Err(SubmitError::Rejected(text)),
+        _ => Err(SubmitError::Retry(text)),
+    }
+}
+
+// "playing_now" listens have no time
+fn listen(scrobble: &Scrobble, listened_at: bool) -> Value {
+    let mut additional_info = json!({
+        "duration_ms": scrobble.duration_secs * 1000,
+        "submission_client": "terminal-spotify",
+        "music_service": "spotify.com",
+    });
+    // ex: spotify:track:6rqhFgbbKwnb9MLmUQDhG6 -> https://open.spotify.com/track/6rqhFgbbKwnb9MLmUQDhG6
+    if let Some(uri) = &scrobble.spotify_uri {
+        let parts: Vec<&str> = uri.split(':').skip(1).collect();
+        additional_info["spotify_id"] =
+            json!(format!("https://open.spotify.com/{}", parts.join("/")));
+    }
+
+    let mut listen = json!({
+        "track_metadata": {
+            "artist_name": scrobble.artist,
+            "track_name": scrobble.title,
+            "release_name": scrobble.album,
+            "additional_info": additional_info,
+        }
+    });
+    if listened_at {
+        listen["listened_at"] = json!(scrobble.timestamp);
+    }
+
+    listen
+}
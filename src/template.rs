@@ -0,0 +1,252 @@
+use terminal_spotify::format_duration;
+
+use crate::events::PlayerState;
+
+///// TEMPLATES /////
+// for status bars, ex: status --format "{icon} {artist} - {title|truncate:30} [{progress}/{duration}]"
+//
+// fields: title, artist, artists, album, progress, duration, device, shuffle,
+// repeat, volume, state, icon
+// filters, after a |, can be chained: truncate:N, pad:N (pads on the right),
+// lpad:N (pads on the left), upper, lower
+// {{ and }} are a literal { and }
+
+pub const FIELDS: &[&str] = &[
+    "title", "artist", "artists", "album", "progress", "duration", "device", "shuffle", "repeat",
+    "volume", "state", "icon",
+];
+
+pub fn render(template: &str, state: &PlayerState) -> Result<String, String> {
+    let mut output = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                output.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                output.push('}');
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return Err(format!("Missing }} after {{{}", placeholder)),
+                    }
+                }
+                output += &placeholder_value(&placeholder, state)?;
+            }
+            '}' => return Err("Found } without a {".to_string()),
+            c => output.push(c),
+        }
+    }
+
+    Ok(output)
+}
+
+// ex: "title|truncate:20|pad:20"
+fn placeholder_value(placeholder: &str, state: &PlayerState) -> Result<String, String> {
+    let mut parts = placeholder.split('|').map(str::trim);
+    let name = parts.next().unwrap_or_default();
+    let mut value = field(name, state).ok_or_else(|| {
+        format!(
+            "Unknown field {{{}}}, use one of: {}",
+            name,
+            FIELDS.join(", ")
+        )
+    })?;
+
+    for filter in parts {
+        value = apply_filter(filter, value)?;
+    }
+
+    Ok(value)
+}
+
+fn field(name: &str, state: &PlayerState) -> Option<String> {
+    let stopped = state.title.is_empty();
+
+    let value = match name {
+        "title" => state.title.clone(),
+        "artist" => state.artists.first().cloned().unwrap_or_default(),
+        "artists" => state.artists.join(", "),
+        "album" => state.album.clone(),
+        "progress" => format_duration(state.progress),
+        "duration" => format_duration(state.duration),
+        "device" => state.device_name.clone().unwrap_or_default(),
+        "shuffle" => String::from(if state.shuffle { "on" } else { "off" }),
+        "repeat" => state.repeat.clone(),
+        "volume" => state
+            .volume
+            .map(|volume| volume.to_string())
+            .unwrap_or_default(),
+        "state" => String::from(match (stopped, state.is_playing) {
+            (true, _) => "stopped",
+            (false, true) => "playing",
+            (false, false) => "paused",
+        }),
+        "icon" => String::from(match (stopped, state.is_playing) {
+            (true, _) => "■",
+            (false, true) => "▶",
+            (false, false) => "⏸",
+        }),
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+fn apply_filter(filter: &str, value: String) -> Result<String, String> {
+    let (name, arg) = match filter.split_once(':') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (filter, None),
+    };
+    let width = || {
+        arg.and_then(|arg| arg.trim().parse::<usize>().ok())
+            .ok_or_else(|| format!("{} needs a width, ex: {}:20", name, name))
+    };
+
+    let length = value.chars().count();
+    let value = match name {
+        "truncate" => {
+            let width = width()?;
+            if length <= width {
+                value
+            } else if width == 0 {
+                String::new()
+            } else {
+                // the … takes the last spot
+                value.chars().take(width - 1).collect::<String>() + "…"
+            }
+        }
+        "pad" => format!("{}{}", value, " ".repeat(width()?.saturating_sub(length))),
+        "lpad" => format!("{}{}", " ".repeat(width()?.saturating_sub(length)), value),
+        "upper" => value.to_uppercase(),
+        "lower" => value.to_lowercase(),
+        _ => {
+            return Err(format!(
+                "Unknown filter {}, use one of: truncate, pad, lpad, upper, lower",
+                name
+            ))
+        }
+    };
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    fn playing() -> PlayerState {
+        PlayerState {
+            track_id: Some(String::from("spotify:track:1")),
+            title: String::from("Harder, Better, Faster, Stronger"),
+            artists: vec![String::from("Daft Punk"), String::from("Kanye West")],
+            album: String::from("Discovery"),
+            duration: TimeDelta::try_seconds(224).unwrap(),
+            is_playing: true,
+            progress: TimeDelta::try_seconds(133).unwrap(),
+            device_name: Some(String::from("Kitchen")),
+            volume: Some(40),
+            repeat: String::from("context"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fills_in_fields() {
+        let rendered = render(
+            "{icon} {artist} - {title} [{progress}/{duration}] {volume}%",
+            &playing(),
+        );
+        assert_eq!(
+            rendered.unwrap(),
+            "▶ Daft Punk - Harder, Better, Faster, Stronger [2:13/3:44] 40%"
+        );
+        assert_eq!(
+            render("{artists} on {device}", &playing()).unwrap(),
+            "Daft Punk, Kanye West on Kitchen"
+        );
+    }
+
+    #[test]
+    fn state_and_icon_when_paused_or_stopped() {
+        let mut paused = playing();
+        paused.is_playing = false;
+        assert_eq!(render("{icon} {state}", &paused).unwrap(), "⏸ paused");
+
+        let stopped = PlayerState::default();
+        assert_eq!(render("{icon} {state}", &stopped).unwrap(), "■ stopped");
+        // missing values are empty, not an error
+        assert_eq!(render("[{device}{volume}]", &stopped).unwrap(), "[]");
+    }
+
+    #[test]
+    fn double_braces_are_literal() {
+        assert_eq!(render("{{title}}", &playing()).unwrap(), "{title}");
+        assert_eq!(render("{{{album}}}", &playing()).unwrap(), "{Discovery}");
+        assert_eq!(render("}}{{", &playing()).unwrap(), "}{");
+    }
+
+    #[test]
+    fn unbalanced_braces_are_errors() {
+        assert!(render("{title", &playing()).is_err());
+        assert!(render("title}", &playing()).is_err());
+        assert!(render("{", &playing()).is_err());
+    }
+
+    #[test]
+    fn unknown_fields_and_filters_are_errors() {
+        let err = render("{nope}", &playing()).unwrap_err();
+        assert!(err.contains("Unknown field {nope}"), "{}", err);
+        let err = render("{title|reverse}", &playing()).unwrap_err();
+        assert!(err.contains("Unknown filter reverse"), "{}", err);
+    }
+
+    #[test]
+    fn truncate() {
+        let state = playing();
+        assert_eq!(render("{album|truncate:5}", &state).unwrap(), "Disc…");
+        assert_eq!(render("{album|truncate:9}", &state).unwrap(), "Discovery");
+        assert_eq!(render("{album|truncate:1}", &state).unwrap(), "…");
+        assert_eq!(render("{album|truncate:0}", &state).unwrap(), "");
+    }
+
+    #[test]
+    fn truncate_counts_characters_not_bytes() {
+        let mut state = playing();
+        state.title = String::from("Ælskår");
+        assert_eq!(render("{title|truncate:4}", &state).unwrap(), "Æls…");
+    }
+
+    #[test]
+    fn filters_need_a_width() {
+        assert!(render("{title|truncate}", &playing()).is_err());
+        assert!(render("{title|pad:}", &playing()).is_err());
+        assert!(render("{title|lpad:-1}", &playing()).is_err());
+    }
+
+    #[test]
+    fn filters_chain() {
+        let state = playing();
+        assert_eq!(render("[{album|pad:11}]", &state).unwrap(), "[Discovery  ]");
+        assert_eq!(
+            render("[{album|lpad:11}]", &state).unwrap(),
+            "[  Discovery]"
+        );
+        // padding never cuts
+        assert_eq!(render("[{album|pad:3}]", &state).unwrap(), "[Discovery]");
+        assert_eq!(
+            render("{ album | truncate:5 | upper | lpad:6 }", &state).unwrap(),
+            " DISC…"
+        );
+        assert_eq!(render("{artist|lower}", &state).unwrap(), "daft punk");
+    }
+}
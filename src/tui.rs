@@ -0,0 +1,637 @@
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    symbols,
+    text::Line,
+    widgets::{Block, LineGauge, List, ListItem, ListState, Paragraph},
+    DefaultTerminal, Frame,
+};
+use rspotify::{
+    model::{AlbumId, PlayableItem, PlaylistId},
+    prelude::*,
+};
+use std::time::{Duration, Instant};
+use terminal_spotify::{disable_prompts_for_now, format_duration};
+use tokio::time::sleep;
+
+use crate::{
+    auth::{reauth, Authorize},
+    command::{parse, Command, CommandError, Reply},
+    config::{AppConfig, Profile},
+    events, find_albums, find_songs, get_playlists, get_queue,
+    handlers::{execute, playing_details, Session},
+    watch::poll_interval,
+    CurrentlyPlaying,
+};
+
+///// TUI /////
+// a full screen front end, playlists on the left, search results or a
+// tracklist in the middle, the queue on the right and the player at the bottom.
+// everything it does to the player goes through the same handlers as the repl
+//
+// keys: tab to move between the panes, up/down (j/k) to pick, enter to open or
+// play, a to add a track to the queue, esc to go back, / to search, : for any
+// repl command, space to pause/resume, n/b next/previous, left/right to seek,
+// r to reload, q to quit
+
+const TICK: Duration = Duration::from_millis(50);
+// spotify needs a moment before it shows what a command did
+const AFTER_COMMAND_POLL: Duration = Duration::from_millis(700);
+
+pub async fn run(
+    mut spotify: impl Authorize + 'static,
+    profile_name: &str,
+    profile: &Profile,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let mut session = Session::start(&spotify, profile_name).await?;
+    let activated = session.activate_default_device(&spotify, profile).await;
+
+    // notifications and scrobbling, stop with the session
+    let config = AppConfig::load()?;
+    let _watcher = events::watch(&spotify, &config);
+
+    // a prompt would draw over the panes, the handlers pick the first match
+    // instead and everything else is picked in the panes
+    let _prompts = disable_prompts_for_now();
+
+    let mut app = App::new(profile_name, config.keys(profile_name).seek_step);
+    match activated {
+        Ok(Some(message)) => app.message = Some(Message::Info(message)),
+        Ok(None) => (),
+        Err(err) => app.message = Some(Message::Error(err.to_string())),
+    }
+    app.reload(&spotify).await;
+
+    let mut terminal = ratatui::init();
+    let res = app.run(&mut terminal, &mut spotify, &mut session).await;
+    ratatui::restore();
+
+    res
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Focus {
+    Playlists,
+    Tracks,
+    Queue,
+}
+
+// what enter does with a row
+#[derive(Debug, Clone)]
+enum Action {
+    // starts from the offset track when it's an album or playlist
+    Play {
+        uri: String,
+        offset: Option<String>,
+    },
+    OpenAlbum {
+        id: AlbumId<'static>,
+        name: String,
+    },
+    OpenPlaylist {
+        id: PlaylistId<'static>,
+        name: String,
+    },
+    Nothing,
+}
+
+#[derive(Debug, Clone)]
+struct Row {
+    text: String,
+    action: Action,
+}
+
+#[derive(Debug, Default, Clone)]
+struct Pane {
+    title: String,
+    rows: Vec<Row>,
+    state: ListState,
+}
+
+impl Pane {
+    fn new(title: &str, rows: Vec<Row>) -> Pane {
+        let mut state = ListState::default();
+        if !rows.is_empty() {
+            state.select(Some(0));
+        }
+        Pane {
+            title: title.to_string(),
+            rows,
+            state,
+        }
+    }
+
+    fn selected(&self) -> Option<&Row> {
+        self.rows.get(self.state.selected()?)
+    }
+}
+
+// what is typed at the bottom
+enum Input {
+    Search(String),
+    Command(String),
+}
+
+enum Message {
+    Info(String),
+    Error(String),
+}
+
+// what a key asks for, the async part happens in run
+enum Task {
+    Run(Command),
+    Search(String),
+    Open(Action),
+    Reload,
+    Quit,
+}
+
+struct App {
+    profile_name: String,
+    // seconds left and right seek, seek_step under [keys]
+    seek_step: i64,
+    playlists: Pane,
+    tracks: Pane,
+    // what the middle showed before an album was opened from the search results
+    previous_tracks: Option<Pane>,
+    queue: Pane,
+    focus: Focus,
+    input: Option<Input>,
+    message: Option<Message>,
+    playing: CurrentlyPlaying,
+    fetched_at: Instant,
+    next_poll: Instant,
+}
+
+impl App {
+    fn new(profile_name: &str, seek_step: i64) -> App {
+        App {
+            profile_name: profile_name.to_string(),
+            seek_step,
+            playlists: Pane::new("Playlists", vec![]),
+            tracks: Pane::new("Tracks", vec![]),
+            previous_tracks: None,
+            queue: Pane::new("Up next", vec![]),
+            focus: Focus::Playlists,
+            input: None,
+            message: None,
+            playing: CurrentlyPlaying::default(),
+            fetched_at: Instant::now(),
+            next_poll: Instant::now(),
+        }
+    }
+
+    async fn run<S: Authorize>(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        spotify: &mut S,
+        session: &mut Session,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        loop {
+            if Instant::now() >= self.next_poll {
+                self.poll(spotify, session).await;
+            }
+
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if !event::poll(Duration::ZERO)? {
+                sleep(TICK).await;
+                continue;
+            }
+            let task = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => self.key(key),
+                _ => None,
+            };
+
+            match task {
+                Some(Task::Run(command)) => {
+                    let queued = matches!(command, Command::Queue(Some(_)));
+                    match execute(spotify, session, command).await {
+                        Ok(Reply::Exit(_)) => return Ok(None),
+                        Ok(Reply::SwitchProfile(next_profile)) => return Ok(Some(next_profile)),
+                        Ok(Reply::Done) => (),
+                        Ok(Reply::Message(text)) | Ok(Reply::Data { text, .. }) => {
+                            self.message = Some(Message::Info(text))
+                        }
+                        Err(err) => self.message = Some(Message::Error(err.to_string())),
+                    }
+                    if queued {
+                        self.load_queue(&*spotify).await;
+                    }
+                    self.next_poll = Instant::now() + AFTER_COMMAND_POLL;
+                }
+                Some(Task::Search(query)) => self.search(&*spotify, &query).await,
+                Some(Task::Open(action)) => self.open(&*spotify, action).await,
+                Some(Task::Reload) => self.reload(&*spotify).await,
+                Some(Task::Quit) => return Ok(None),
+                None => (),
+            }
+        }
+    }
+
+    async fn poll(&mut self, spotify: &impl Authorize, session: &mut Session) {
+        session.forget_playing();
+        match session.now_playing(spotify).await {
+            Ok(playing) => {
+                let track_changed = playing.song_name != self.playing.song_name;
+                self.next_poll = Instant::now() + poll_interval(&playing);
+                self.playing = playing;
+                self.fetched_at = Instant::now();
+                if track_changed {
+                    self.load_queue(spotify).await;
+                }
+            }
+            Err(err) => {
+                self.next_poll = Instant::now() + poll_interval(&CurrentlyPlaying::default());
+                self.message = Some(Message::Error(err.to_string()));
+            }
+        }
+    }
+
+    async fn reload(&mut self, spotify: &impl Authorize) {
+        let rows = get_playlists(spotify)
+            .await
+            .into_iter()
+            .map(|playlist| Row {
+                text: playlist.name.clone(),
+                action: Action::OpenPlaylist {
+                    id: playlist.id,
+                    name: playlist.name,
+                },
+            })
+            .collect();
+        self.playlists = Pane::new("Playlists", rows);
+        self.load_queue(spotify).await;
+    }
+
+    async fn load_queue(&mut self, spotify: &impl Authorize) {
+        let queue = match get_queue(spotify).await {
+            Ok(queue) => queue,
+            Err(err) => {
+                self.message = Some(Message::Error(err.to_string()));
+                return;
+            }
+        };
+
+        let rows = queue
+            .into_iter()
+            .map(|item| Row {
+                text: format!("{} - {}", item.name, item.artists.join(", ")),
+                action: Action::Nothing,
+            })
+            .collect();
+        self.queue = Pane::new("Up next", rows);
+    }
+
+    async fn search(&mut self, spotify: &impl Authorize, query: &str) {
+        let songs = find_songs(spotify, query)
+            .await
+            .into_iter()
+            .map(|song| Row {
+                text: format!("♪ {} - {}", song.song_name, song.artists.join(", ")),
+                action: Action::Play {
+                    uri: song.id.uri(),
+                    offset: None,
+                },
+            });
+        let albums = find_albums(spotify, query)
+            .await
+            .into_iter()
+            .map(|album| Row {
+                text: format!("◉ {} - {}", album.name, album.artists.join(", ")),
+                action: Action::OpenAlbum {
+                    id: album.id,
+                    name: album.name,
+                },
+            });
+
+        let rows: Vec<Row> = songs.chain(albums).collect();
+        if rows.is_empty() {
+            self.message = Some(Message::Error(format!("Nothing found for {}", query)));
+            return;
+        }
+
+        self.tracks = Pane::new(&format!("Search: {}", query), rows);
+        self.previous_tracks = None;
+        self.focus = Focus::Tracks;
+    }
+
+    // albums and playlists open their tracklist in the middle
+    async fn open(&mut self, spotify: &impl Authorize, action: Action) {
+        let (name, uri, tracks) = match action {
+            Action::OpenAlbum { id, name } => {
+                let tracks = reauth(spotify, || {
+                    spotify.album_track_manual(id.clone(), None, Some(50), None)
+                })
+                .await
+                .map(|page| {
+                    page.items
+                        .into_iter()
+                        .filter_map(|track| {
+                            Some((track.id?.uri(), track.name, artist_names(&track.artists)))
+                        })
+                        .collect::<Vec<_>>()
+                });
+                (name, id.uri(), tracks)
+            }
+            Action::OpenPlaylist { id, name } => {
+                let tracks = reauth(spotify, || {
+                    spotify.playlist_items_manual(id.clone(), None, None, Some(100), None)
+                })
+                .await
+                .map(|page| {
+                    page.items
+                        .into_iter()
+                        .filter_map(|item| match item.track? {
+                            PlayableItem::Track(track) => {
+                                Some((track.id?.uri(), track.name, artist_names(&track.artists)))
+                            }
+                            PlayableItem::Episode(episode) => {
+                                Some((episode.id.uri(), episode.name, episode.show.name))
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                });
+                (name, id.uri(), tracks)
+            }
+            _ => return,
+        };
+
+        let tracks = match tracks {
+            Ok(tracks) => tracks,
+            Err(err) => {
+                let err =
+                    CommandError::failed(&format!("Could not get the tracks of {}", name), err);
+                self.message = Some(Message::Error(err.to_string()));
+                return;
+            }
+        };
+
+        let mut rows = vec![Row {
+            text: format!("▶ Play {}", name),
+            action: Action::Play {
+                uri: uri.clone(),
+                offset: None,
+            },
+        }];
+        rows.extend(tracks.into_iter().map(|(track_uri, title, artists)| Row {
+            text: format!("{} - {}", title, artists),
+            action: Action::Play {
+                uri: uri.clone(),
+                offset: Some(track_uri),
+            },
+        }));
+
+        // esc goes back to the search results
+        if self.tracks.title.starts_with("Search: ") {
+            self.previous_tracks = Some(self.tracks.clone());
+        }
+        self.tracks = Pane::new(&name, rows);
+        self.focus = Focus::Tracks;
+    }
+
+    fn key(&mut self, key: KeyEvent) -> Option<Task> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Some(Task::Quit);
+        }
+
+        if let Some(input) = &mut self.input {
+            let text = match input {
+                Input::Search(text) | Input::Command(text) => text,
+            };
+            match key.code {
+                KeyCode::Char(c) => text.push(c),
+                KeyCode::Backspace => {
+                    text.pop();
+                }
+                KeyCode::Esc => self.input = None,
+                KeyCode::Enter => {
+                    return match self.input.take() {
+                        Some(Input::Search(query)) if !query.trim().is_empty() => {
+                            Some(Task::Search(query.trim().to_string()))
+                        }
+                        Some(Input::Command(command)) if !command.trim().is_empty() => {
+                            match parse(&command) {
+                                // exit would ask on stdin under the panes
+                                Ok(Command::Exit) => Some(Task::Quit),
+                                Ok(command) => Some(Task::Run(command)),
+                                Err(err) => {
+                                    self.message = Some(Message::Error(err.to_string()));
+                                    None
+                                }
+                            }
+                        }
+                        _ => None,
+                    };
+                }
+                _ => (),
+            }
+            return None;
+        }
+
+        let pane = match self.focus {
+            Focus::Playlists => &mut self.playlists,
+            Focus::Tracks => &mut self.tracks,
+            Focus::Queue => &mut self.queue,
+        };
+
+        match key.code {
+            KeyCode::Char('q') => return Some(Task::Quit),
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Focus::Playlists => Focus::Tracks,
+                    Focus::Tracks => Focus::Queue,
+                    Focus::Queue => Focus::Playlists,
+                }
+            }
+            KeyCode::BackTab => {
+                self.focus = match self.focus {
+                    Focus::Playlists => Focus::Queue,
+                    Focus::Tracks => Focus::Playlists,
+                    Focus::Queue => Focus::Tracks,
+                }
+            }
+            KeyCode::Down | KeyCode::Char('j') => pane.state.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => pane.state.select_previous(),
+            KeyCode::Enter => {
+                return match pane.selected()?.action.clone() {
+                    Action::Play { uri, offset } => {
+                        Some(Task::Run(Command::PlayUri { uri, offset }))
+                    }
+                    Action::Nothing => None,
+                    action => Some(Task::Open(action)),
+                }
+            }
+            // the track of the row, not the album or playlist it's in
+            KeyCode::Char('a') => {
+                return match &pane.selected()?.action {
+                    Action::Play {
+                        offset: Some(track),
+                        ..
+                    }
+                    | Action::Play { uri: track, .. }
+                        if track.starts_with("spotify:track:") =>
+                    {
+                        Some(Task::Run(Command::Queue(Some(track.clone()))))
+                    }
+                    _ => None,
+                }
+            }
+            KeyCode::Esc => {
+                if let Some(previous) = self.previous_tracks.take() {
+                    self.tracks = previous;
+                }
+            }
+            KeyCode::Char('/') => self.input = Some(Input::Search(String::new())),
+            KeyCode::Char(':') => self.input = Some(Input::Command(String::new())),
+            KeyCode::Char(' ') => return Some(Task::Run(Command::Toggle)),
+            KeyCode::Char('n') => return Some(Task::Run(Command::Next)),
+            KeyCode::Char('b') => return Some(Task::Run(Command::Prev)),
+            KeyCode::Left => return Some(Task::Run(Command::Back(Some(self.seek_step)))),
+            KeyCode::Right => return Some(Task::Run(Command::Forward(Some(self.seek_step)))),
+            KeyCode::Char('r') => return Some(Task::Reload),
+            _ => (),
+        }
+
+        None
+    }
+
+    ///// DRAWING /////
+    fn draw(&mut self, frame: &mut Frame) {
+        let [panes, player, bottom] = Layout::vertical([
+            Constraint::Min(5),
+            Constraint::Length(5),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [playlists, tracks, queue] = Layout::horizontal([
+            Constraint::Percentage(25),
+            Constraint::Percentage(45),
+            Constraint::Percentage(30),
+        ])
+        .areas(panes);
+
+        let focus = self.focus;
+        draw_pane(
+            frame,
+            playlists,
+            &mut self.playlists,
+            focus == Focus::Playlists,
+        );
+        draw_pane(frame, tracks, &mut self.tracks, focus == Focus::Tracks);
+        draw_pane(frame, queue, &mut self.queue, focus == Focus::Queue);
+
+        self.draw_player(frame, player);
+
+        let line = match (&self.input, &self.message) {
+            (Some(Input::Search(text)), _) => Line::raw(format!("/{}▏", text)),
+            (Some(Input::Command(text)), _) => Line::raw(format!(":{}▏", text)),
+            (None, Some(Message::Info(text))) => Line::raw(first_line(text)),
+            (None, Some(Message::Error(text))) => {
+                Line::styled(first_line(text), Style::new().fg(Color::Red))
+            }
+            (None, None) => Line::styled(
+                "/ search · : command · space pause · n/b next/prev · a queue · q quit",
+                Style::new().fg(Color::DarkGray),
+            ),
+        };
+        frame.render_widget(Paragraph::new(line), bottom);
+    }
+
+    fn draw_player(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().title(format!(" {} ", self.profile_name));
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        let playing = self.playing.moved_along(self.fetched_at.elapsed());
+        if playing.song_name.is_empty() {
+            frame.render_widget(
+                Paragraph::new("You are not listening to anything at the moment"),
+                inner,
+            );
+            return;
+        }
+
+        let [title, gauge, details] = Layout::vertical([Constraint::Length(1); 3]).areas(inner);
+
+        let mut text = format!(
+            "{} {} - {}",
+            if playing.is_playing { "▶" } else { "⏸" },
+            playing.artists.join(", "),
+            playing.song_name
+        );
+        if !playing.album.is_empty() {
+            text += &format!(" ({})", playing.album);
+        }
+        frame.render_widget(
+            Paragraph::new(text).style(Style::new().add_modifier(Modifier::BOLD)),
+            title,
+        );
+
+        let progress = playing.progress.unwrap_or_default();
+        let ratio = if playing.duration.num_milliseconds() > 0 {
+            (progress.num_milliseconds() as f64 / playing.duration.num_milliseconds() as f64)
+                .clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        frame.render_widget(
+            LineGauge::default()
+                .filled_style(Style::new().fg(Color::Green))
+                .line_set(symbols::line::THICK)
+                .label(format!(
+                    "{} / {}",
+                    format_duration(progress),
+                    format_duration(playing.duration)
+                ))
+                .ratio(ratio),
+            gauge,
+        );
+
+        frame.render_widget(
+            Paragraph::new(playing_details(&playing)).style(Style::new().fg(Color::DarkGray)),
+            details,
+        );
+    }
+}
+
+fn draw_pane(frame: &mut Frame, area: Rect, pane: &mut Pane, focused: bool) {
+    let border = if focused {
+        Style::new().fg(Color::Green)
+    } else {
+        Style::new()
+    };
+    let items: Vec<ListItem> = pane
+        .rows
+        .iter()
+        .map(|row| ListItem::new(row.text.as_str()))
+        .collect();
+
+    let list = List::new(items)
+        .block(
+            Block::bordered()
+                .title(format!(" {} ", pane.title))
+                .border_style(border),
+        )
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, area, &mut pane.state);
+}
+
+// messages like the device list are more than a line
+fn first_line(text: &str) -> String {
+    let mut lines = text.lines();
+    let first = lines.next().unwrap_or_default();
+    match lines.count() {
+        0 => first.to_string(),
+        more => format!("{} (+{} lines)", first, more),
+    }
+}
+
+fn artist_names(artists: &[rspotify::model::SimplifiedArtist]) -> String {
+    artists
+        .iter()
+        .map(|artist| artist.name.clone())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
@@ -0,0 +1,161 @@
+use colored::Colorize;
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyEventKind},
+    execute, queue,
+    terminal::{self, ClearType},
+};
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+use terminal_spotify::can_prompt;
+use tokio::time::sleep;
+
+use crate::{
+    auth::Authorize,
+    command::{CommandError, Reply},
+    get_queue,
+    handlers::{status, Session},
+    list_queue, CurrentlyPlaying, QueueItem,
+};
+
+///// WATCH /////
+// takes over the terminal and redraws what is playing and what is up next until
+// a key is pressed, the progress moves along on its own between polls
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+// how often spotify is asked while something plays and while nothing does
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const PAUSED_POLL_INTERVAL: Duration = Duration::from_secs(15);
+// not more often than this, even when a track is about to end
+const MIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// the queue only changes with the track or when something is queued
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_secs(30);
+// how much of the queue is shown
+const QUEUE_LENGTH: usize = 5;
+
+pub async fn watch(spotify: &impl Authorize, session: &mut Session) -> Result<Reply, CommandError> {
+    if !can_prompt() {
+        return Err(CommandError::Usage("watch needs a terminal".to_string()));
+    }
+
+    let _screen = Screen::enter()
+        .map_err(|err| CommandError::failed("Could not take over the terminal", err))?;
+
+    let mut playing = CurrentlyPlaying::default();
+    let mut fetched_at = Instant::now();
+    let mut next_poll = Instant::now();
+    let mut queue: Vec<QueueItem> = vec![];
+    let mut queue_fetched_at: Option<Instant> = None;
+    let mut error = None;
+
+    while !key_pressed() {
+        if Instant::now() >= next_poll {
+            session.forget_playing();
+            match session.now_playing(spotify).await {
+                Ok(now_playing) => {
+                    let track_changed = now_playing.song_name != playing.song_name;
+                    let queue_outdated = queue_fetched_at
+                        .is_none_or(|fetched_at| fetched_at.elapsed() >= QUEUE_POLL_INTERVAL);
+
+                    if track_changed || queue_outdated {
+                        // an empty queue is better than an old one
+                        queue = get_queue(spotify).await.unwrap_or_default();
+                        queue_fetched_at = Some(Instant::now());
+                    }
+
+                    next_poll = Instant::now() + poll_interval(&now_playing);
+                    playing = now_playing;
+                    fetched_at = Instant::now();
+                    error = None;
+                }
+                Err(err) => {
+                    next_poll = Instant::now() + PAUSED_POLL_INTERVAL;
+                    error = Some(err.to_string());
+                }
+            }
+        }
+
+        draw(
+            &playing.moved_along(fetched_at.elapsed()),
+            &queue,
+            error.as_deref(),
+        )
+        .map_err(|err| CommandError::failed("Could not draw the screen", err))?;
+
+        sleep(REDRAW_INTERVAL).await;
+    }
+
+    Ok(Reply::Done)
+}
+
+// right after the track ends when that is soon so the next one shows up, less
+// often while paused
+pub fn poll_interval(playing: &CurrentlyPlaying) -> Duration {
+    if !playing.is_playing {
+        return PAUSED_POLL_INTERVAL;
+    }
+
+    let left = (playing.duration - playing.progress.unwrap_or_default())
+        .to_std()
+        .unwrap_or_default();
+    (left + Duration::from_millis(500)).clamp(MIN_POLL_INTERVAL, POLL_INTERVAL)
+}
+
+fn key_pressed() -> bool {
+    while event::poll(Duration::ZERO).unwrap_or(false) {
+        if let Ok(Event::Key(key)) = event::read() {
+            if key.kind == KeyEventKind::Press {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn draw(playing: &CurrentlyPlaying, queue: &[QueueItem], error: Option<&str>) -> io::Result<()> {
+    let mut text = status(playing, false);
+    if !queue.is_empty() {
+        text += &format!(
+            "\n\n{}",
+            list_queue(&queue[..queue.len().min(QUEUE_LENGTH)])
+        );
+    }
+    if let Some(error) = error {
+        text += &format!("\n\n{}", error.red());
+    }
+    text += &format!("\n\n{}", "Press any key to stop".dimmed());
+
+    // over the old screen line by line instead of clearing it first, clearing
+    // makes it flicker
+    let mut stdout = io::stdout();
+    queue!(stdout, cursor::MoveTo(0, 0))?;
+    for line in text.lines() {
+        // raw mode doesn't go back to the start of the line on its own
+        write!(stdout, "{}", line)?;
+        queue!(stdout, terminal::Clear(ClearType::UntilNewLine))?;
+        write!(stdout, "\r\n")?;
+    }
+    queue!(stdout, terminal::Clear(ClearType::FromCursorDown))?;
+    stdout.flush()
+}
+
+// raw mode and the alternate screen, the terminal is put back however watch ends
+struct Screen;
+
+impl Screen {
+    fn enter() -> io::Result<Screen> {
+        terminal::enable_raw_mode()?;
+        let screen = Screen;
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}